@@ -0,0 +1,437 @@
+//! ncm-api未提供的接口
+//!
+//! 直接请求网易云音乐的 `/api/` 接口，与 `MusicApi` 共用同一份cookie
+use anyhow::{Context, Result, bail};
+use ncm_api::MusicApi;
+use reqwest::{Client, ClientBuilder, header};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_with::{DefaultOnNull, serde_as};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+use url::Url;
+
+const BASE_URL: &str = "https://music.163.com";
+/// 每次请求歌曲详情的最大歌曲数
+const SONG_DETAIL_CHUNK_SIZE: usize = 100;
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36";
+
+#[derive(Clone)]
+pub struct ExtraApi {
+    client: Client,
+    /// 每次请求时读取最新的登录状态，响应中的cookie也写回 `MusicApi`
+    api: Arc<Mutex<MusicApi>>,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    code: i64,
+    data: T,
+}
+
+/// 歌曲下载链接
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SongUrl {
+    /// 下载链接，无权限时为空
+    pub url: Option<String>,
+    /// 码率(bps)
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub br: u64,
+    /// 文件大小(字节)
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub size: u64,
+    /// 文件格式，如 `mp3`、`flac`
+    #[serde(rename = "type", default)]
+    pub extension: Option<String>,
+    /// 实际获取到的音质
+    #[serde(default)]
+    pub level: Option<String>,
+    /// 文件时长(毫秒)
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub time: u64,
+    /// 试听片段信息，不为空时只能获取试听片段
+    #[serde(default)]
+    pub free_trial_info: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct SongDetailResponse {
+    code: i64,
+    #[serde(default)]
+    songs: Vec<SongDetail>,
+}
+
+#[derive(Deserialize)]
+struct AlbumResponse {
+    code: i64,
+    album: AlbumDetail,
+    #[serde(default)]
+    songs: Vec<AlbumSong>,
+}
+
+/// 专辑中的歌曲，只用于统计碟片数
+#[derive(Deserialize)]
+struct AlbumSong {
+    #[serde(default)]
+    cd: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ArtistResponse {
+    code: i64,
+    artist: Artist,
+}
+
+/// 歌手
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Artist {
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub id: u64,
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub name: String,
+    /// 歌手图片，只有歌手详情中才有
+    #[serde(default)]
+    pub pic_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LyricResponse {
+    code: i64,
+    #[serde(default)]
+    lrc: Option<LyricContent>,
+    #[serde(default)]
+    tlyric: Option<LyricContent>,
+    #[serde(default)]
+    romalrc: Option<LyricContent>,
+    #[serde(default)]
+    yrc: Option<LyricContent>,
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+struct LyricContent {
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    lyric: String,
+}
+
+/// 歌曲的各种歌词，没有时为空字符串
+#[derive(Debug, Clone, Default)]
+pub struct SongLyrics {
+    /// 原文歌词(LRC)
+    pub lyric: String,
+    /// 翻译歌词(LRC)
+    pub translation: String,
+    /// 罗马音歌词(LRC)
+    pub romaji: String,
+    /// 逐字歌词(yrc)
+    pub karaoke: String,
+}
+
+#[derive(Deserialize)]
+struct PlaylistResponse {
+    code: i64,
+    playlist: PlaylistTracks,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistTracks {
+    #[serde(default)]
+    track_ids: Vec<PlaylistTrackId>,
+}
+
+/// 歌单中的歌曲Id和添加时间
+#[serde_as]
+#[derive(Deserialize)]
+struct PlaylistTrackId {
+    id: u64,
+    /// 添加时间(毫秒时间戳)
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    at: i64,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    code: i64,
+    #[serde(default)]
+    result: Option<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    #[serde(default)]
+    songs: Vec<SongDetail>,
+}
+
+/// 歌曲所属的专辑
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SongAlbum {
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub id: u64,
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub name: String,
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub pic_url: String,
+}
+
+/// 歌曲详情
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct SongDetail {
+    pub id: u64,
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "al", default)]
+    pub album: Option<SongAlbum>,
+    /// 时长(毫秒)
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(rename = "dt", default)]
+    pub duration: u64,
+    /// 在专辑中的曲目序号，未知时为0
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(rename = "no", default)]
+    pub track_number: u32,
+    /// 碟片序号，如 `01`
+    #[serde(rename = "cd", default)]
+    pub disc: Option<String>,
+    /// 发行时间(毫秒时间戳)，未知时为0
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(rename = "publishTime", default)]
+    pub publish_time: i64,
+    /// 歌手，不含图片
+    #[serde(rename = "ar", default)]
+    pub artists: Vec<Artist>,
+}
+
+/// 专辑详情
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumDetail {
+    /// 专辑歌手
+    #[serde(default)]
+    pub artist: Option<Artist>,
+    /// 发行时间(毫秒时间戳)，未知时为0
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub publish_time: i64,
+    /// 专辑中的歌曲数
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub size: u32,
+    /// 专辑风格，如 `流行`
+    #[serde(default)]
+    pub tags: Option<String>,
+    /// 碟片数，由专辑中的歌曲统计得到，未知时为0
+    #[serde(skip)]
+    pub disc_total: u32,
+}
+
+impl ExtraApi {
+    pub fn new(api: Arc<Mutex<MusicApi>>, timeout: Duration) -> Result<Self> {
+        let client = ClientBuilder::new()
+            .timeout(timeout)
+            .user_agent(USER_AGENT)
+            .build()
+            .context("Failed to create HTTP client")?;
+        Ok(Self { client, api })
+    }
+
+    /// `MusicApi` 中当前的cookie
+    async fn cookie(&self, url: &Url) -> String {
+        let api = self.api.lock().await;
+        let cookie_jar = api.cookie_jar();
+        let store = cookie_jar.lock().unwrap();
+        store
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join("; ")
+    }
+
+    /// 把服务器刷新的cookie写回 `MusicApi`
+    async fn store_cookies(&self, response: &reqwest::Response) {
+        let api = self.api.lock().await;
+        let cookie_jar = api.cookie_jar();
+        let mut store = cookie_jar.lock().unwrap();
+        for value in response.headers().get_all(header::SET_COOKIE) {
+            if let Ok(value) = value.to_str()
+                && let Err(e) = store.parse(value, response.url())
+            {
+                log::warn!("Failed to store cookie: {}", e);
+            }
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T> {
+        let url = Url::parse(BASE_URL)?.join(path)?;
+        let cookie = self.cookie(&url).await;
+        let response = self
+            .client
+            .get(url.clone())
+            .query(query)
+            .header(header::REFERER, BASE_URL)
+            .header(header::COOKIE, cookie)
+            .send()
+            .await
+            .with_context(|| format!("Failed to send request to {}", url))?;
+        self.store_cookies(&response).await;
+        if !response.status().is_success() {
+            bail!("HTTP error: {}", response.status());
+        }
+        response
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse response from {}", url))
+    }
+
+    /// 获取指定音质的歌曲下载链接，音质不可用时服务器会返回较低的音质
+    pub async fn song_url(&self, id: u64, level: &str) -> Result<SongUrl> {
+        let response: Response<Vec<SongUrl>> = self
+            .get(
+                "/api/song/enhance/player/url/v1",
+                &[
+                    ("ids", format!("[{}]", id)),
+                    ("level", level.to_string()),
+                    ("encodeType", "flac".to_string()),
+                ],
+            )
+            .await?;
+        if response.code != 200 {
+            bail!("API error: {}", response.code);
+        }
+        response
+            .data
+            .into_iter()
+            .next()
+            .with_context(|| format!("No url returned for song {}", id))
+    }
+
+    /// 批量获取歌曲详情
+    pub async fn songs_detail(&self, ids: &[u64]) -> Result<Vec<SongDetail>> {
+        let mut result = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(SONG_DETAIL_CHUNK_SIZE) {
+            let c: Vec<serde_json::Value> = chunk
+                .iter()
+                .map(|id| serde_json::json!({ "id": id }))
+                .collect();
+            let response: SongDetailResponse = self
+                .get(
+                    "/api/v3/song/detail",
+                    &[("c", serde_json::Value::Array(c).to_string())],
+                )
+                .await?;
+            if response.code != 200 {
+                bail!("API error: {}", response.code);
+            }
+            result.extend(response.songs);
+        }
+        Ok(result)
+    }
+
+    /// 获取原文、翻译、罗马音和逐字歌词
+    pub async fn lyrics(&self, id: u64) -> Result<SongLyrics> {
+        let response: LyricResponse = self
+            .get(
+                "/api/song/lyric/v1",
+                &[
+                    ("id", id.to_string()),
+                    ("lv", "-1".to_string()),
+                    ("tv", "-1".to_string()),
+                    ("rv", "-1".to_string()),
+                    ("yv", "-1".to_string()),
+                ],
+            )
+            .await?;
+        if response.code != 200 {
+            bail!("API error: {}", response.code);
+        }
+        let text = |v: Option<LyricContent>| v.map(|v| v.lyric).unwrap_or_default();
+        Ok(SongLyrics {
+            lyric: text(response.lrc),
+            translation: text(response.tlyric),
+            romaji: text(response.romalrc),
+            karaoke: text(response.yrc),
+        })
+    }
+
+    /// 获取歌单中每首歌曲的添加时间(毫秒时间戳)
+    pub async fn playlist_added_times(&self, id: u64) -> Result<HashMap<u64, i64>> {
+        let response: PlaylistResponse = self
+            .get(
+                "/api/v6/playlist/detail",
+                &[("id", id.to_string()), ("n", "0".to_string())],
+            )
+            .await?;
+        if response.code != 200 {
+            bail!("API error: {}", response.code);
+        }
+        Ok(response
+            .playlist
+            .track_ids
+            .into_iter()
+            .filter(|v| v.at > 0)
+            .map(|v| (v.id, v.at))
+            .collect())
+    }
+
+    /// 按关键词搜索歌曲，返回的歌曲信息与歌曲详情相同
+    pub async fn search_songs(&self, keywords: &str, limit: usize) -> Result<Vec<SongDetail>> {
+        let response: SearchResponse = self
+            .get(
+                "/api/cloudsearch/pc",
+                &[
+                    ("s", keywords.to_string()),
+                    ("type", "1".to_string()),
+                    ("limit", limit.to_string()),
+                    ("offset", "0".to_string()),
+                ],
+            )
+            .await?;
+        if response.code != 200 {
+            bail!("API error: {}", response.code);
+        }
+        Ok(response.result.map(|v| v.songs).unwrap_or_default())
+    }
+
+    /// 获取歌手详情
+    pub async fn artist(&self, id: u64) -> Result<Artist> {
+        let response: ArtistResponse = self.get(&format!("/api/v1/artist/{}", id), &[]).await?;
+        if response.code != 200 {
+            bail!("API error: {}", response.code);
+        }
+        Ok(response.artist)
+    }
+
+    /// 获取专辑详情
+    pub async fn album(&self, id: u64) -> Result<AlbumDetail> {
+        let response: AlbumResponse = self.get(&format!("/api/v1/album/{}", id), &[]).await?;
+        if response.code != 200 {
+            bail!("API error: {}", response.code);
+        }
+        let mut album = response.album;
+        album.disc_total = response
+            .songs
+            .iter()
+            .filter_map(|v| v.cd.as_deref()?.trim().parse::<u32>().ok())
+            .max()
+            .unwrap_or_default();
+        Ok(album)
+    }
+}
@@ -1,5 +1,70 @@
 use anyhow::Context;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::export::ExportFormat;
 use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "网易云音乐歌单下载工具")]
+pub struct Args {
+    /// 配置文件路径
+    #[arg(long, global = true, env = "NCMD_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// cookie文件路径
+    #[arg(long, global = true, env = "NCMD_COOKIE")]
+    pub cookie: Option<PathBuf>,
+    /// 歌单文件夹的保存目录
+    #[arg(long, global = true, env = "NCMD_OUTPUT_DIR")]
+    pub output_dir: Option<PathBuf>,
+    /// 覆盖配置项，格式为 配置项=值，可以重复使用
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// 下载歌单（默认）
+    Download,
+    /// 从浏览器导出的cookie导入登录状态
+    ImportCookie {
+        /// Netscape格式的cookies.txt文件路径，或`MUSIC_U=...`形式的cookie字符串
+        source: String,
+    },
+    /// 为已有的歌曲文件重新写入标签和封面，不重新下载音频
+    Retag {
+        /// 歌曲文件所在的文件夹，默认为歌单文件夹的保存目录
+        dir: Option<PathBuf>,
+        /// 只显示会修改的标签，不写入文件
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// 导出歌单中的歌曲列表，不下载任何文件
+    Export {
+        /// 歌单Id，不填时运行后输入
+        id: Option<u64>,
+        /// 导出的格式，可以重复使用，默认使用配置中的 metadata_formats
+        #[arg(long = "format", value_enum)]
+        formats: Vec<ExportFormat>,
+    },
+    /// 查看当前登录的账号信息
+    Whoami,
+    /// 退出登录并删除保存的cookie
+    Logout,
+    /// 配置相关操作
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// 显示最终生效的配置及每一项的来源
+    Show,
+}
 #[allow(unused)]
 pub async fn print(s: &str) -> anyhow::Result<()> {
     tokio::io::stdout()
@@ -0,0 +1,297 @@
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::CollisionStrategy,
+    manifest::Manifest,
+    metadata,
+    playlist::AUDIO_EXTENSIONS,
+    template::RenderedPath,
+    track::Track,
+    util::{self, FilenameRules},
+};
+
+/// 为每首歌曲分配不重复的路径
+///
+/// 路径已被本次任务中的其它歌曲占用、清单中记录为其它歌曲，或者存在清单中没有记录、标签中的歌曲Id也不同的同名文件时视为重名。
+/// 输出目录中还没有清单时(如旧版本下载的文件夹)，同名文件视为同一首歌曲
+pub struct PathRegistry {
+    output_dir: PathBuf,
+    strategy: CollisionStrategy,
+    rules: FilenameRules,
+    max_length: usize,
+    manifest: Manifest,
+    /// 运行前输出目录中是否已有清单
+    has_manifest: bool,
+    /// 本次任务中已分配的路径(不含扩展名，不区分大小写时为小写) => 歌曲Id
+    claimed: HashMap<PathBuf, u64>,
+    /// 清单是否有新的记录需要保存
+    changed: bool,
+}
+
+impl PathRegistry {
+    pub fn new(
+        output_dir: &Path,
+        strategy: CollisionStrategy,
+        rules: FilenameRules,
+        max_length: usize,
+    ) -> Self {
+        Self {
+            output_dir: output_dir.to_path_buf(),
+            strategy,
+            rules,
+            max_length,
+            manifest: Manifest::load(output_dir),
+            has_manifest: Manifest::path(output_dir).is_file(),
+            claimed: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    /// 为歌曲分配路径，重名时按配置的方式在文件名后加上区分的内容
+    ///
+    /// 分配的路径在下载成功后才通过 [`PathRegistry::record`] 记录到清单中
+    pub async fn claim(&mut self, rendered: RenderedPath, track: &Track) -> RenderedPath {
+        // 之前已经以区分后的文件名下载过时继续使用该文件名
+        let previous = self.manifest.files_of(track.id).find_map(|path| {
+            let stem = path.file_name()?.to_string_lossy().to_string();
+            (path.parent() == Some(rendered.dir.as_path())
+                && stem.starts_with(&rendered.stem)
+                && self
+                    .claimed
+                    .get(&self.claim_key(&path))
+                    .is_none_or(|&owner| owner == track.id))
+            .then_some(stem)
+        });
+        let mut candidates = Vec::new();
+        if self.strategy == CollisionStrategy::Album && !track.album.trim().is_empty() {
+            candidates.push(format!("{} ({})", rendered.stem, track.album));
+        }
+        if self.strategy != CollisionStrategy::Counter {
+            candidates.push(format!("{} ({})", rendered.stem, track.id));
+        }
+        let counters = (2..).map(|n| format!("{} ({})", rendered.stem, n));
+        let candidates = previous
+            .into_iter()
+            .chain(std::iter::once(rendered.stem.clone()))
+            .chain(candidates)
+            .chain(counters)
+            .map(|stem| util::truncate_filename(&stem, self.max_length, &self.rules));
+        for stem in candidates {
+            if !self.is_taken(&rendered.dir, &stem, track.id).await {
+                let key = rendered.dir.join(&stem);
+                self.claimed.insert(self.claim_key(&key), track.id);
+                return RenderedPath {
+                    dir: rendered.dir,
+                    stem,
+                };
+            }
+        }
+        unreachable!()
+    }
+
+    /// 清单中记录的该歌曲的文件(不含扩展名)
+    pub fn previous_files(&self, id: u64) -> Vec<PathBuf> {
+        self.manifest.files_of(id).collect()
+    }
+
+    /// 在清单中记录下载成功的文件
+    pub fn record(&mut self, rendered: &RenderedPath, id: u64) {
+        self.manifest.insert(&rendered.dir.join(&rendered.stem), id);
+        self.changed = true;
+    }
+
+    /// 保存清单，下次运行时可以识别本次下载的文件，没有新的记录时不写入
+    pub fn save(&self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.manifest.save(&self.output_dir)
+    }
+
+    fn claim_key(&self, key: &Path) -> PathBuf {
+        if self.rules.case_insensitive() {
+            PathBuf::from(key.to_string_lossy().to_lowercase())
+        } else {
+            key.to_path_buf()
+        }
+    }
+
+    async fn is_taken(&self, dir: &Path, stem: &str, id: u64) -> bool {
+        let key = dir.join(stem);
+        if let Some(&owner) = self.claimed.get(&self.claim_key(&key)) {
+            return owner != id;
+        }
+        let owners = if self.rules.case_insensitive() {
+            self.manifest.get_ignore_case(&key)
+        } else {
+            self.manifest.get(&key).into_iter().collect()
+        };
+        if !owners.is_empty() {
+            return owners.iter().any(|&owner| owner != id);
+        }
+        let dir = self.output_dir.join(dir);
+        let stem = stem.to_string();
+        let ignore_case = self.rules.case_insensitive();
+        let has_manifest = self.has_manifest;
+        tokio::task::spawn_blocking(move || {
+            let files = files_on_disk(&dir, &stem, ignore_case);
+            if files.is_empty() || !has_manifest {
+                return false;
+            }
+            // 歌词等同名文件跟随歌曲文件，只按歌曲文件标签中的歌曲Id判断
+            let audio: Vec<&PathBuf> = files.iter().filter(|v| is_audio(v)).collect();
+            audio.is_empty()
+                || audio
+                    .iter()
+                    .any(|path| metadata::read_song_id(path) != Some(id))
+        })
+        .await
+        .unwrap_or(true)
+    }
+}
+
+/// 文件夹中该文件名(不含扩展名)的文件，包括试听片段
+fn files_on_disk(dir: &Path, stem: &str, ignore_case: bool) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let preview_stem = format!("{} [preview]", stem);
+    let same = |a: &str, b: &str| {
+        if ignore_case {
+            a.to_lowercase() == b.to_lowercase()
+        } else {
+            a == b
+        }
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_stem()
+                .map(|v| v.to_string_lossy())
+                .is_some_and(|v| same(&v, stem) || same(&v, &preview_stem))
+        })
+        .collect()
+}
+
+fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .map(|v| v.to_string_lossy().to_lowercase())
+        .is_some_and(|v| AUDIO_EXTENSIONS.contains(&v.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: u64, album: &str) -> Track {
+        Track {
+            id,
+            title: "Song".to_string(),
+            album: album.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn rendered() -> RenderedPath {
+        RenderedPath {
+            dir: PathBuf::from("List"),
+            stem: "Song - A".to_string(),
+        }
+    }
+
+    fn registry(name: &str, strategy: CollisionStrategy) -> (PathBuf, PathRegistry) {
+        let dir = std::env::temp_dir().join(format!("ncmd-collision-{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("List")).unwrap();
+        let registry = PathRegistry::new(&dir, strategy, FilenameRules::default(), 200);
+        (dir, registry)
+    }
+
+    #[tokio::test]
+    async fn test_collision_in_job() {
+        let (dir, mut registry) = registry("job", CollisionStrategy::Album);
+        assert_eq!(
+            registry.claim(rendered(), &track(1, "A")).await.stem,
+            "Song - A"
+        );
+        assert_eq!(
+            registry.claim(rendered(), &track(1, "A")).await.stem,
+            "Song - A"
+        );
+        assert_eq!(
+            registry.claim(rendered(), &track(2, "Live")).await.stem,
+            "Song - A (Live)"
+        );
+        assert_eq!(
+            registry.claim(rendered(), &track(3, "Live")).await.stem,
+            "Song - A (3)"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_collision_with_existing_files() {
+        let dir = std::env::temp_dir().join("ncmd-collision-existing");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("List")).unwrap();
+        Manifest::default().save(&dir).unwrap();
+        let mut registry = PathRegistry::new(
+            &dir,
+            CollisionStrategy::Counter,
+            FilenameRules::default(),
+            200,
+        );
+        std::fs::write(dir.join("List/Song - A.flac"), b"").unwrap();
+        std::fs::write(dir.join("List/Song - A (2) [preview].mp3"), b"").unwrap();
+        let claimed = registry.claim(rendered(), &track(1, "")).await;
+        assert_eq!(claimed.stem, "Song - A (3)");
+        registry.record(&claimed, 1);
+        registry.save().unwrap();
+
+        // 清单中记录的歌曲再次下载时使用原来的文件名
+        let mut registry =
+            PathRegistry::new(&dir, CollisionStrategy::Id, FilenameRules::default(), 200);
+        std::fs::write(dir.join("List/Song - A (3).flac"), b"").unwrap();
+        assert_eq!(
+            registry.claim(rendered(), &track(1, "")).await.stem,
+            "Song - A (3)"
+        );
+        assert_eq!(
+            registry.claim(rendered(), &track(5, "")).await.stem,
+            "Song - A (5)"
+        );
+        // 不区分大小写时只有大小写不同的文件名也视为重名
+        let mut lower = rendered();
+        lower.stem = "song - a".to_string();
+        assert_eq!(
+            registry.claim(lower, &track(6, "")).await.stem,
+            "song - a (6)"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_collision_without_manifest() {
+        // 旧版本下载的文件夹中没有清单，同名文件视为同一首歌曲
+        let (dir, mut registry) = registry("no-manifest", CollisionStrategy::Id);
+        std::fs::write(dir.join("List/Song - A.flac"), b"").unwrap();
+        std::fs::write(dir.join("List/Song - A.lrc"), b"").unwrap();
+        let claimed = registry.claim(rendered(), &track(1, "")).await;
+        assert_eq!(claimed.stem, "Song - A");
+        registry.save().unwrap();
+        assert!(!Manifest::path(&dir).is_file());
+        registry.record(&claimed, 1);
+        registry.save().unwrap();
+        assert!(Manifest::path(&dir).is_file());
+        assert_eq!(
+            registry.claim(rendered(), &track(2, "")).await.stem,
+            "Song - A (2)"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,14 +1,44 @@
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use serde_with::{DurationMilliSeconds, serde_as};
+use serde_with::{DefaultOnNull, DurationMilliSeconds, serde_as};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
 use std::time::Duration;
-const BITRATE_LEVELS: [&str; 8] = [
-    "higer", "exhigh", "lossless", "hires", "jyeffect", "sky", "dolby", "jymaster",
+
+use crate::export::ExportFormat;
+use crate::karaoke::KaraokeFormat;
+use crate::lyrics::{CreditLines, LyricVariant, LyricsFormat, LyricsStyle};
+use crate::playlist::{FailedTracks, PlaylistFormat};
+use crate::template::PathTemplate;
+use crate::util::{self, FilenameRules, FilenameTarget};
+/// 按音质从低到高排列
+const BITRATE_LEVELS: [&str; 9] = [
+    "standard", "higher", "exhigh", "lossless", "hires", "jyeffect", "sky", "dolby", "jymaster",
+];
+/// 不含音效音质、按保真度从低到高排列，用于判断最低音质
+const FIDELITY_LEVELS: [&str; 6] = [
+    "standard", "higher", "exhigh", "lossless", "hires", "jymaster",
 ];
+/// 环绕声、全景声等音效音质，都是有损编码，判断最低音质时视为极高
+const EFFECT_LEVELS: [&str; 3] = ["jyeffect", "sky", "dolby"];
+const EFFECT_FIDELITY_LEVEL: &str = "exhigh";
+const AUTO_BITRATE_LEVEL: &str = "auto";
+
+const ENV_PREFIX: &str = "NCMD_";
+const MAX_CONCURRENCY: usize = 64;
+const MIN_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
 
 const DEFAULT_CONFIG: &str = r##"max_bitrate_level: "exhigh"
-#max_bitrate_level:下载歌曲的最高质量，不填写内容默认为可下载的最高质量
+#max_bitrate_level:下载歌曲的最高质量，不填写内容或填写auto默认为可下载的最高质量
+#也可以填写按优先级排列的列表，例如 [jymaster, hires, lossless, exhigh]，
+#歌曲没有前一个音质时依次尝试后面的音质
 #可填内容:
+# auto => 可下载的最高音质
+# standard => 标准
 # higher => 较高
 # exhigh=>极高
 # lossless=>无损
@@ -17,6 +47,78 @@ const DEFAULT_CONFIG: &str = r##"max_bitrate_level: "exhigh"
 # sky => 沉浸环绕声
 # dolby => 杜比全景声
 # jymaster => 超清母带
+min_bitrate_level:
+#min_bitrate_level:下载歌曲的最低质量，不填写内容则不限制
+#可填内容:standard、higher、exhigh、lossless、hires、jymaster
+#环绕声等音效音质按有损处理，只满足 exhigh 及以下的最低质量
+min_bitrate_policy: "skip"
+#min_bitrate_policy:实际获取到的音质低于min_bitrate_level时的处理方式
+#可填内容:
+# skip => 跳过该歌曲
+# fail => 视为下载失败
+# flag => 仍然下载，并在下载完成后列出
+preview_policy: "skip"
+#preview_policy:没有权限的歌曲只能获取试听片段时的处理方式
+#可填内容:
+# skip => 跳过该歌曲
+# save => 仍然保存，文件名后加上 [preview]
+dir_template: "{playlist}"
+#dir_template:歌曲所在文件夹的模板，可以用 / 分隔多级文件夹
+file_template: "{title}<({translated})> - {artists}"
+#file_template:歌曲文件名的模板(不含扩展名)
+#可用的占位符:
+# {title} => 歌曲名
+# {translated} => 歌曲名的翻译
+# {artists} => 所有歌手
+# {artist} => 第一个歌手
+# {album} => 专辑名
+# {album_artist} => 专辑歌手
+# {track} => 曲目序号
+# {disc} => 碟片序号
+# {year} => 发行年份
+# {playlist} => 歌单名
+# {index} => 在歌单中的序号
+# {id} => 歌曲Id
+# {quality} => 实际获取到的音质
+#用 <> 括起来的部分中有占位符为空时整段省略，例如 <({translated})>
+filename_target: "portable"
+#filename_target:文件名需要兼容的文件系统
+#可填内容:
+# portable => 同时兼容以下所有文件系统
+# windows => Windows(NTFS)
+# macos => macOS
+# linux => Linux
+# fat32 => U盘、SD卡等FAT32/exFAT文件系统
+filename_replacement: ""
+#filename_replacement:替换文件名中非法字符的内容，不填写内容则直接删除非法字符
+collision_strategy: "id"
+#collision_strategy:不同歌曲的文件名相同，或者与已有的其它文件重名时的处理方式
+#可填内容:
+# id => 在文件名后加上歌曲Id，例如 歌名 (123456)
+# album => 在文件名后加上专辑名，专辑名为空或仍然重名时加上歌曲Id
+# counter => 在文件名后加上序号，例如 歌名 (2)
+playlist_formats: [m3u8]
+#playlist_formats:下载完成后在输出目录中按歌单顺序生成的播放列表文件，不填写内容则不生成
+#可填内容:列表，可包含
+# m3u8 => 扩展M3U播放列表
+# xspf => XSPF播放列表
+playlist_failed_tracks: "omit"
+#playlist_failed_tracks:播放列表中如何处理未下载的歌曲
+#可填内容:
+# omit => 不写入
+# comment => 以注释的形式写入
+mode: "download"
+#mode:运行模式
+#可填内容:
+# download => 按 download_songs、download_lyrics 下载歌曲和歌词
+# lyrics => 只为输出目录中已有的歌曲文件补全缺少的歌词文件，不下载歌曲，不创建文件夹
+# metadata => 只把歌单中歌曲的信息导出为 metadata_formats 中的格式，不下载任何文件
+metadata_formats: [json, csv]
+#metadata_formats:metadata模式和export命令导出的格式，文件保存在输出目录中，以歌单名命名
+#可填内容:列表，可包含
+# json => JSON
+# csv => CSV(UTF-8)
+# txt => 每行一首 歌手 - 标题 的文本，可用于其它音乐平台的歌单导入工具
 download_songs: true
 #download_songs:是否下载歌曲
 #可填内容:
@@ -27,6 +129,57 @@ download_lyrics: false
 #可填内容:
 # true => 下载歌词
 # false => 不下载歌词
+lyrics_variants: []
+#lyrics_variants:除原文外还需要保存的歌词，不填写内容则只保存原文
+#可填内容:列表，可包含
+# translation => 翻译
+# romaji => 罗马音
+lyrics_style: "separate"
+#lyrics_style:翻译、罗马音歌词的保存方式
+#可填内容:
+# separate => 保存为单独的文件，例如 歌名.translation.lrc、歌名.romaji.lrc
+# interleave => 写在原文的下一行，时间与原文相同
+# inline => 与原文写在同一行，例如 [00:01.00]原文 / 翻译
+lyrics_format: "lrc"
+#lyrics_format:歌词文件的格式，歌词会统一时间格式、按时间排序并去掉重复的信息标签
+#可填内容:
+# lrc => LRC歌词
+# srt => SRT字幕
+# txt => 不带时间的纯文本
+lyrics_credits: "keep"
+#lyrics_credits:歌词开头的作词、作曲等署名的处理方式
+#可填内容:
+# keep => 保留
+# strip => 删除
+karaoke_lyrics: []
+#karaoke_lyrics:下载歌词时额外导出的逐字歌词，歌曲没有逐字歌词时跳过，不填写内容则不导出
+#可填内容:列表，可包含
+# lrc => 带逐字时间的增强LRC，例如 歌名.karaoke.lrc
+# ass => 带卡拉OK效果的ASS字幕，例如 歌名.ass
+cover_size: 0
+#cover_size:写入歌曲文件的封面边长(像素)，由服务器缩放
+#可填内容:正整数，例如 1400，填写0则使用原图
+folder_cover: ""
+#folder_cover:在专辑文件夹中保存专辑封面的文件名(不含扩展名)，不填写内容则不保存
+#只在文件夹模板的最后一级使用了 {album} 时生效
+#可填内容:例如 cover、folder
+folder_cover_size: 0
+#folder_cover_size:专辑文件夹中封面的边长(像素)
+#可填内容:正整数，填写0则使用原图
+artist_image: ""
+#artist_image:在歌手文件夹中保存歌手图片的文件名(不含扩展名)，不填写内容则不保存
+#只在文件夹模板中使用了 {album_artist} 或 {artist} 时生效
+#可填内容:例如 artist、folder
+overwrite_images: false
+#overwrite_images:是否覆盖文件夹中已有的封面和歌手图片
+#可填内容:
+# true => 覆盖
+# false => 已有同名图片(jpg/jpeg/png)时跳过
+embed_lyrics: false
+#embed_lyrics:是否把歌词写入歌曲文件
+#可填内容:
+# true => mp3写入USLT(纯文本)和SYLT(逐行时间)，flac/ogg/opus写入LYRICS(LRC)和UNSYNCEDLYRICS(纯文本)，m4a/ape写入LRC
+# false => 不写入
 concurrency: 3
 #concurrency:同时下载的任务数
 #可填内容:正整数(不建议设置太大)
@@ -39,34 +192,191 @@ retry_delay: 1000
 timeout: 30000
 #timeout:下载超时时间
 #可填内容:正整数(单位：毫秒)
+guest: false
+#guest:是否以游客模式运行
+#可填内容:
+# true => 不登录，仅下载无需账号即可获取的歌曲和歌词
+# false => 使用手机号登录
 "##;
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
-    pub max_bitrate_level: String,
+    pub max_bitrate_level: BitratePreference,
+    #[serde(default)]
+    pub min_bitrate_level: Option<String>,
+    #[serde(default)]
+    pub min_bitrate_policy: MinBitratePolicy,
+    #[serde(default)]
+    pub preview_policy: PreviewPolicy,
+    #[serde(default = "default_dir_template")]
+    pub dir_template: String,
+    #[serde(default = "default_file_template")]
+    pub file_template: String,
+    #[serde(default)]
+    pub filename_target: FilenameTarget,
+    #[serde(default)]
+    pub filename_replacement: String,
+    #[serde(default)]
+    pub collision_strategy: CollisionStrategy,
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default = "default_playlist_formats")]
+    pub playlist_formats: Vec<PlaylistFormat>,
+    #[serde(default)]
+    pub playlist_failed_tracks: FailedTracks,
+    #[serde(default)]
+    pub mode: JobMode,
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default = "default_metadata_formats")]
+    pub metadata_formats: Vec<ExportFormat>,
     pub download_songs: bool,
     pub download_lyrics: bool,
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub lyrics_variants: Vec<LyricVariant>,
+    #[serde(default)]
+    pub lyrics_style: LyricsStyle,
+    #[serde(default)]
+    pub lyrics_format: LyricsFormat,
+    #[serde(default)]
+    pub lyrics_credits: CreditLines,
+    #[serde_as(as = "DefaultOnNull")]
+    #[serde(default)]
+    pub karaoke_lyrics: Vec<KaraokeFormat>,
+    #[serde(default)]
+    pub cover_size: u32,
+    #[serde(default)]
+    pub folder_cover: String,
+    #[serde(default)]
+    pub folder_cover_size: u32,
+    #[serde(default)]
+    pub artist_image: String,
+    #[serde(default)]
+    pub overwrite_images: bool,
+    #[serde(default)]
+    pub embed_lyrics: bool,
     pub concurrency: usize,
     pub retry: usize,
     #[serde_as(as = "DurationMilliSeconds<u64>")]
     pub retry_delay: Duration,
     #[serde_as(as = "DurationMilliSeconds<u64>")]
     pub timeout: Duration,
+    #[serde(default)]
+    pub guest: bool,
 }
 
 #[allow(unused)]
 impl Config {
     pub fn load(content: &str) -> Result<Config, ConfigError> {
         let config: Config = serde_yaml::from_str(content).with_context(|| "配置解析错误")?;
-        if !BITRATE_LEVELS
-            .iter()
-            .any(|&x| x == config.max_bitrate_level)
+        if let Err((key, message)) = config.validate() {
+            return Err(ConfigError::InvalidConfig(format!(
+                "配置项 {} {}",
+                key, message
+            )));
+        }
+        Ok(config)
+    }
+
+    /// 检查各配置项的取值范围，出错时返回配置项名称和错误信息
+    fn validate(&self) -> Result<(), (&'static str, String)> {
+        if let Err(level) = self.max_bitrate_level.check() {
+            return Err((
+                "max_bitrate_level",
+                format!(
+                    "的值 {} 无效，可填内容：{}, {}",
+                    level,
+                    AUTO_BITRATE_LEVEL,
+                    BITRATE_LEVELS.join(", ")
+                ),
+            ));
+        }
+        if let Some(level) = self.min_bitrate_level()
+            && normalize_level(level).is_none_or(|v| !FIDELITY_LEVELS.contains(&v))
         {
-            return Err(ConfigError::InvalidConfig(
-                "max_bitrate_level设置有误".into(),
+            return Err((
+                "min_bitrate_level",
+                format!(
+                    "的值 {} 无效，可填内容：{}",
+                    level,
+                    FIDELITY_LEVELS.join(", ")
+                ),
             ));
         }
-        Ok(config)
+        if let Err(e) = PathTemplate::new(&self.dir_template, "{title}") {
+            return Err(("dir_template", e));
+        }
+        if let Err(e) = PathTemplate::new("", &self.file_template) {
+            return Err(("file_template", e));
+        }
+        let rules = self.filename_rules();
+        if let Some(c) = self
+            .filename_replacement
+            .chars()
+            .find(|&c| rules.is_forbidden(c))
+        {
+            return Err((
+                "filename_replacement",
+                format!("不能包含文件名中不允许的字符 {:?}", c),
+            ));
+        }
+        for (key, name) in [
+            ("folder_cover", &self.folder_cover),
+            ("artist_image", &self.artist_image),
+        ] {
+            if util::sanitize_filename(name, &rules) != *name {
+                return Err((key, format!("的值 {} 不是有效的文件名", name)));
+            }
+        }
+        if self.concurrency == 0 || self.concurrency > MAX_CONCURRENCY {
+            return Err((
+                "concurrency",
+                format!("必须在 1 到 {} 之间", MAX_CONCURRENCY),
+            ));
+        }
+        if self.timeout < MIN_TIMEOUT || self.timeout > MAX_TIMEOUT {
+            return Err((
+                "timeout",
+                format!(
+                    "必须在 {} 到 {} 毫秒之间",
+                    MIN_TIMEOUT.as_millis(),
+                    MAX_TIMEOUT.as_millis()
+                ),
+            ));
+        }
+        if self.retry_delay > MAX_RETRY_DELAY {
+            return Err((
+                "retry_delay",
+                format!("不能超过 {} 毫秒", MAX_RETRY_DELAY.as_millis()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// 最低音质，未设置时返回 `None`
+    pub fn min_bitrate_level(&self) -> Option<&str> {
+        self.min_bitrate_level
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    }
+
+    /// 本次运行是否下载歌曲
+    pub fn downloads_songs(&self) -> bool {
+        self.mode == JobMode::Download && self.download_songs
+    }
+
+    /// 本次运行是否保存歌词
+    pub fn downloads_lyrics(&self) -> bool {
+        match self.mode {
+            JobMode::Download => self.download_lyrics,
+            JobMode::Lyrics => true,
+            JobMode::Metadata => false,
+        }
+    }
+
+    /// 文件名清理规则
+    pub fn filename_rules(&self) -> FilenameRules {
+        FilenameRules::new(self.filename_target, &self.filename_replacement)
     }
 
     pub fn generate_default() -> &'static str {
@@ -78,12 +388,460 @@ impl Config {
     }
 }
 
+fn default_dir_template() -> String {
+    "{playlist}".to_string()
+}
+
+fn default_file_template() -> String {
+    "{title}<({translated})> - {artists}".to_string()
+}
+
+fn default_playlist_formats() -> Vec<PlaylistFormat> {
+    vec![PlaylistFormat::M3u8]
+}
+
+fn default_metadata_formats() -> Vec<ExportFormat> {
+    vec![ExportFormat::Json, ExportFormat::Csv]
+}
+
+/// 音质偏好
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BitratePreference {
+    /// 单个音质，没有该音质时由服务器返回较低的音质
+    Single(Option<String>),
+    /// 按优先级排列的音质列表
+    List(Vec<String>),
+}
+
+impl BitratePreference {
+    /// 按优先级列出需要依次尝试的音质
+    pub fn levels(&self) -> Vec<&'static str> {
+        let items: Vec<&str> = match self {
+            BitratePreference::Single(v) => vec![v.as_deref().unwrap_or_default()],
+            BitratePreference::List(v) => v.iter().map(String::as_str).collect(),
+        };
+        let mut levels = Vec::new();
+        for item in items {
+            let candidates = match normalize_level(item) {
+                Some(level) => vec![level],
+                None if is_auto_level(item) => BITRATE_LEVELS.iter().rev().copied().collect(),
+                None => Vec::new(),
+            };
+            for level in candidates {
+                if !levels.contains(&level) {
+                    levels.push(level);
+                }
+            }
+        }
+        levels
+    }
+
+    /// 用户指定的首选音质，第一项为 `auto` 时返回 `None`
+    pub fn preferred(&self) -> Option<&'static str> {
+        let first = match self {
+            BitratePreference::Single(v) => v.as_deref().unwrap_or_default(),
+            BitratePreference::List(v) => v.first()?,
+        };
+        normalize_level(first)
+    }
+
+    /// 检查是否都是已知的音质，出错时返回无效的值
+    fn check(&self) -> Result<(), String> {
+        let items: Vec<&str> = match self {
+            BitratePreference::Single(v) => vec![v.as_deref().unwrap_or_default()],
+            BitratePreference::List(v) if v.is_empty() => return Err("[]".to_string()),
+            BitratePreference::List(v) => v.iter().map(String::as_str).collect(),
+        };
+        match items
+            .into_iter()
+            .find(|item| normalize_level(item).is_none() && !is_auto_level(item))
+        {
+            Some(item) => Err(item.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 运行模式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobMode {
+    /// 下载歌曲和歌词
+    #[default]
+    Download,
+    /// 只为已有的歌曲文件补全歌词
+    Lyrics,
+    /// 只导出歌曲信息
+    Metadata,
+}
+
+/// 实际音质低于最低音质时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MinBitratePolicy {
+    /// 跳过该歌曲
+    #[default]
+    Skip,
+    /// 视为下载失败
+    Fail,
+    /// 仍然下载并标记
+    Flag,
+}
+
+/// 只能获取试听片段时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewPolicy {
+    /// 跳过该歌曲
+    #[default]
+    Skip,
+    /// 保存试听片段，文件名后加上 `[preview]`
+    Save,
+}
+
+/// 文件名重复时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionStrategy {
+    /// 加上歌曲Id
+    #[default]
+    Id,
+    /// 加上专辑名
+    Album,
+    /// 加上序号
+    Counter,
+}
+
+/// 音质按保真度的等级，数值越大音质越高，音效音质与极高相同，未知的音质返回 `None`
+pub fn fidelity_rank(level: &str) -> Option<usize> {
+    let level = normalize_level(level)?;
+    let level = if EFFECT_LEVELS.contains(&level) {
+        EFFECT_FIDELITY_LEVEL
+    } else {
+        level
+    };
+    FIDELITY_LEVELS.iter().position(|&v| v == level)
+}
+
+fn is_auto_level(level: &str) -> bool {
+    let level = level.trim();
+    level.is_empty() || level == AUTO_BITRATE_LEVEL
+}
+
+/// 把音质名称规范为 `BITRATE_LEVELS` 中的值，无效的音质返回 `None`
+pub fn normalize_level(level: &str) -> Option<&'static str> {
+    let level = match level.trim() {
+        // 兼容旧版本配置中的拼写错误
+        "higer" => "higher",
+        v => v,
+    };
+    BITRATE_LEVELS.iter().find(|&&v| v == level).copied()
+}
+
+/// 配置项的来源
+#[derive(Debug, Clone)]
+pub enum ConfigSource {
+    /// 内置默认值
+    Default,
+    /// 系统配置文件
+    System(PathBuf),
+    /// 用户配置文件
+    User(PathBuf),
+    /// 当前目录下的配置文件
+    Local(PathBuf),
+    /// `--config` 指定的配置文件
+    Custom(PathBuf),
+    /// `NCMD_*` 环境变量
+    Env(String),
+    /// `--set` 命令行参数，记录参数的内容
+    Cli(String),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "默认值"),
+            ConfigSource::System(path) => write!(f, "系统配置文件 {}", path.display()),
+            ConfigSource::User(path) => write!(f, "用户配置文件 {}", path.display()),
+            ConfigSource::Local(path) => write!(f, "当前目录配置文件 {}", path.display()),
+            ConfigSource::Custom(path) => write!(f, "指定的配置文件 {}", path.display()),
+            ConfigSource::Env(name) => write!(f, "环境变量 {}", name),
+            ConfigSource::Cli(item) => write!(f, "命令行参数 --set {}", item),
+        }
+    }
+}
+
+/// 分层加载的配置，后合并的层覆盖先合并的层
+///
+/// 优先级从低到高依次为：内置默认值、系统配置文件、用户配置文件、当前目录下的配置文件、
+/// `NCMD_*` 环境变量、`--set` 命令行参数
+#[derive(Debug)]
+pub struct ConfigLayers {
+    values: Mapping,
+    sources: BTreeMap<String, ConfigSource>,
+    /// 配置项的值在配置文件中的行号和列号
+    locations: BTreeMap<String, (usize, usize)>,
+}
+
+impl Default for ConfigLayers {
+    fn default() -> Self {
+        let values: Mapping = serde_yaml::from_str(DEFAULT_CONFIG).unwrap();
+        let sources = values
+            .keys()
+            .filter_map(Value::as_str)
+            .map(|key| (key.to_string(), ConfigSource::Default))
+            .collect();
+        Self {
+            values,
+            sources,
+            locations: BTreeMap::new(),
+        }
+    }
+}
+
+impl ConfigLayers {
+    /// 合并一个YAML格式的配置文件
+    pub fn merge_yaml(&mut self, content: &str, source: ConfigSource) -> Result<(), ConfigError> {
+        // 空文件或只有注释的文件解析为Null
+        let layer: Option<Mapping> = match serde_yaml::from_str(content) {
+            Ok(v) => v,
+            Err(e) => {
+                let location = match e.location() {
+                    Some(location) => {
+                        format!("第{}行第{}列", location.line(), location.column())
+                    }
+                    None => String::new(),
+                };
+                return Err(ConfigError::InvalidConfig(format!(
+                    "{}{}存在语法错误：{}",
+                    source, location, e
+                )));
+            }
+        };
+        let layer = layer.unwrap_or_default();
+        let locations = key_locations(content);
+        let error_location = type_error_location(content, &layer);
+        for (key, value) in layer {
+            let Some(key) = key.as_str() else {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "{}中的配置项名称必须为字符串",
+                    source
+                )));
+            };
+            // 值的类型错误时使用serde_yaml给出的位置，可以定位到列表或映射中具体的值
+            let index = locations.iter().position(|(name, _, _)| name == key);
+            let location = index.map(|i| {
+                let (_, line, value_location) = &locations[i];
+                let next_line = locations.get(i + 1).map(|(_, line, _)| *line);
+                match error_location {
+                    Some(error) if error.0 >= *line && next_line.is_none_or(|v| error.0 < v) => {
+                        error
+                    }
+                    _ => *value_location,
+                }
+            });
+            self.set(key, value, source.clone(), location)?;
+        }
+        Ok(())
+    }
+
+    /// 合并 `NCMD_<配置项>` 形式的环境变量，只处理已知的配置项
+    pub fn merge_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), ConfigError> {
+        let keys: Vec<String> = self.sources.keys().cloned().collect();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_lowercase();
+            if keys.contains(&key) {
+                self.set(&key, parse_scalar(&value), ConfigSource::Env(name), None)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 合并 `配置项=值` 形式的命令行参数
+    pub fn merge_overrides(&mut self, overrides: &[String]) -> Result<(), ConfigError> {
+        for item in overrides {
+            let Some((key, value)) = item.split_once('=') else {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "--set {} 格式错误，应为 配置项=值",
+                    item
+                )));
+            };
+            self.set(
+                key.trim(),
+                parse_scalar(value.trim()),
+                ConfigSource::Cli(item.clone()),
+                None,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// 生成最终生效的配置
+    pub fn build(&self) -> Result<Config, ConfigError> {
+        let config: Config = serde_yaml::from_value(Value::Mapping(self.values.clone()))
+            .with_context(|| "配置解析错误")?;
+        if let Err((key, message)) = config.validate() {
+            return Err(self.field_error(key, &message));
+        }
+        Ok(config)
+    }
+
+    /// 列出最终生效的配置项及其来源
+    pub fn show(&self) -> String {
+        let mut result = String::new();
+        for (key, value) in &self.values {
+            let Some(key) = key.as_str() else {
+                continue;
+            };
+            let value = serde_yaml::to_string(value).unwrap_or_default();
+            result.push_str(&format!(
+                "{}: {}  # {}\n",
+                key,
+                value.trim(),
+                self.sources[key]
+            ));
+        }
+        result
+    }
+
+    /// 设置单个配置项，未知的配置项和类型错误的值会直接报错
+    fn set(
+        &mut self,
+        key: &str,
+        value: Value,
+        source: ConfigSource,
+        location: Option<(usize, usize)>,
+    ) -> Result<(), ConfigError> {
+        self.sources.insert(key.to_string(), source);
+        match location {
+            Some(location) => self.locations.insert(key.to_string(), location),
+            None => self.locations.remove(key),
+        };
+        let defaults: Mapping = serde_yaml::from_str(DEFAULT_CONFIG).unwrap();
+        if !defaults.contains_key(key) {
+            return Err(self.field_error(key, "不是已知的配置项"));
+        }
+        // 在默认配置的基础上单独替换这一项，以便将类型错误定位到具体的配置项
+        let mut single = defaults;
+        single.insert(Value::String(key.to_string()), value.clone());
+        if let Err(e) = serde_yaml::from_value::<Config>(Value::Mapping(single)) {
+            return Err(self.field_error(key, &format!("的值类型错误：{}", e)));
+        }
+        self.values.insert(Value::String(key.to_string()), value);
+        Ok(())
+    }
+
+    /// 生成带有来源和位置的配置项错误
+    fn field_error(&self, key: &str, message: &str) -> ConfigError {
+        let source = match self.sources.get(key) {
+            Some(source) => source.to_string(),
+            None => ConfigSource::Default.to_string(),
+        };
+        let location = match self.locations.get(key) {
+            Some((line, column)) => format!("第{}行第{}列", line, column),
+            None => String::new(),
+        };
+        ConfigError::InvalidConfig(format!(
+            "{}{}：配置项 {} {}",
+            source, location, key, message
+        ))
+    }
+}
+
+/// 查找顶层配置项在YAML文本中的位置，返回 (配置项名称, 配置项所在行, 值的行号和列号)，均从1开始
+///
+/// 值写在下一行(如多行列表)时返回下一个非空行的位置
+fn key_locations(content: &str) -> Vec<(String, usize, (usize, usize))> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut result = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.starts_with([' ', '\t', '#', '-']) {
+            continue;
+        }
+        let Some((key, rest)) = split_key(line) else {
+            continue;
+        };
+        let value = rest.trim_start();
+        let inline = (index + 1, line.chars().count() - value.chars().count() + 1);
+        let location = if value.is_empty() || value.starts_with('#') {
+            // 值写在后面的行中时使用第一个缩进的行或列表项
+            lines[index + 1..]
+                .iter()
+                .enumerate()
+                .find(|(_, line)| {
+                    let trimmed = line.trim_start();
+                    !trimmed.is_empty() && !trimmed.starts_with('#')
+                })
+                .filter(|(_, line)| line.starts_with([' ', '\t', '-']))
+                .map(|(offset, line)| {
+                    let indent = line.chars().count() - line.trim_start().chars().count();
+                    (index + offset + 2, indent + 1)
+                })
+                .unwrap_or(inline)
+        } else {
+            inline
+        };
+        result.push((key, index + 1, location));
+    }
+    result
+}
+
+/// 取出一行中的配置项名称(可以带引号)和冒号之后的内容
+fn split_key(line: &str) -> Option<(String, &str)> {
+    let (key, rest) = match line.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = line[1..].find(quote)? + 1;
+            (
+                &line[1..end],
+                line[end + 1..].trim_start().strip_prefix(':')?,
+            )
+        }
+        _ => {
+            let end = line
+                .find(": ")
+                .or_else(|| line.ends_with(':').then(|| line.len() - 1))?;
+            (line[..end].trim_end(), &line[end + 1..])
+        }
+    };
+    Some((key.to_string(), rest))
+}
+
+/// 用serde_yaml直接解析配置文件的文本，以得到类型错误的准确位置
+///
+/// 配置文件中没有的配置项使用默认值，追加在文本末尾，不影响已有内容的行号
+fn type_error_location(content: &str, layer: &Mapping) -> Option<(usize, usize)> {
+    let defaults: Mapping = serde_yaml::from_str(DEFAULT_CONFIG).unwrap();
+    let missing: Mapping = defaults
+        .into_iter()
+        .filter(|(key, _)| !layer.contains_key(key))
+        .collect();
+    let text = if missing.is_empty() {
+        content.to_string()
+    } else {
+        format!("{}\n{}", content, serde_yaml::to_string(&missing).ok()?)
+    };
+    let location = serde_yaml::from_str::<Config>(&text).err()?.location()?;
+    (location.line() <= content.lines().count()).then_some((location.line(), location.column()))
+}
+
+/// 按YAML标量解析环境变量和命令行参数中的值，使 `3`、`true` 等得到正确的类型
+fn parse_scalar(value: &str) -> Value {
+    serde_yaml::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ConfigError {
     #[error("配置格式错误: {0}")]
     InvalidConfig(String),
 
-    #[error("配置解析错误: {0}")]
+    #[error("配置解析错误: {0:#}")]
     Parse(#[from] anyhow::Error),
 }
 
@@ -91,6 +849,135 @@ pub enum ConfigError {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_layers() {
+        let mut layers = ConfigLayers::default();
+        layers
+            .merge_yaml(
+                "concurrency: 5\nretry: 1\n",
+                ConfigSource::Local(PathBuf::from("config.yml")),
+            )
+            .unwrap();
+        layers
+            .merge_env([("NCMD_RETRY".to_string(), "2".to_string())])
+            .unwrap();
+        layers
+            .merge_overrides(&["download_lyrics=true".to_string()])
+            .unwrap();
+        let config = layers.build().unwrap();
+        assert_eq!(config.concurrency, 5);
+        assert_eq!(config.retry, 2);
+        assert!(config.download_lyrics);
+        assert_eq!(config.max_bitrate_level.levels(), ["exhigh"]);
+    }
+
+    #[test]
+    fn test_field_errors() {
+        let source = ConfigSource::Local(PathBuf::from("config.yml"));
+        let mut layers = ConfigLayers::default();
+        let e = layers
+            .merge_yaml("retry: 1\ntimeout: abc\n", source.clone())
+            .unwrap_err();
+        assert!(e.to_string().contains("第2行第10列"));
+        assert!(e.to_string().contains("timeout"));
+
+        let e = ConfigLayers::default()
+            .merge_yaml(
+                "retry: 1\nlyrics_variants:\n  - translation\n  - unknown\n",
+                source.clone(),
+            )
+            .unwrap_err();
+        assert!(e.to_string().contains("第4行第5列"));
+
+        let e = ConfigLayers::default()
+            .merge_overrides(&["retry=abc".to_string()])
+            .unwrap_err();
+        assert!(e.to_string().contains("--set retry=abc"));
+
+        let e = layers
+            .merge_yaml("unknown_key: 1\n", source.clone())
+            .unwrap_err();
+        assert!(e.to_string().contains("unknown_key"));
+
+        let mut layers = ConfigLayers::default();
+        layers
+            .merge_yaml("retry: 1\nconcurrency: 0\n", source.clone())
+            .unwrap();
+        let e = layers.build().unwrap_err();
+        assert!(e.to_string().contains("第2行第14列"));
+        assert!(e.to_string().contains("concurrency"));
+
+        let e = layers.merge_yaml("retry: [1\n", source).unwrap_err();
+        assert!(e.to_string().contains("语法错误"));
+    }
+
+    #[test]
+    fn test_key_locations() {
+        let content = "\"retry\": 1\nlyrics_variants:\n  # 注释\n  - translation\nmin_bitrate_level:\nmode: lyrics\n";
+        assert_eq!(
+            key_locations(content),
+            [
+                ("retry".to_string(), 1, (1, 10)),
+                ("lyrics_variants".to_string(), 2, (4, 3)),
+                ("min_bitrate_level".to_string(), 5, (5, 19)),
+                ("mode".to_string(), 6, (6, 7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bitrate_preference() {
+        let levels = |content: &str| {
+            Config::load(&DEFAULT_CONFIG.replace("max_bitrate_level: \"exhigh\"", content))
+                .map(|config| config.max_bitrate_level.levels())
+        };
+        assert_eq!(levels("max_bitrate_level: higer").unwrap(), ["higher"]);
+        assert_eq!(
+            levels("max_bitrate_level: [hires, lossless]").unwrap(),
+            ["hires", "lossless"]
+        );
+        assert_eq!(
+            levels("max_bitrate_level:").unwrap(),
+            BITRATE_LEVELS.iter().rev().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            levels("max_bitrate_level: [jymaster, auto]").unwrap().len(),
+            BITRATE_LEVELS.len()
+        );
+        assert!(levels("max_bitrate_level: [hires, best]").is_err());
+        let preferred = |content: &str| {
+            Config::load(&DEFAULT_CONFIG.replace("max_bitrate_level: \"exhigh\"", content))
+                .unwrap()
+                .max_bitrate_level
+                .preferred()
+        };
+        assert_eq!(preferred("max_bitrate_level: auto"), None);
+        assert_eq!(preferred("max_bitrate_level:"), None);
+        assert_eq!(
+            preferred("max_bitrate_level: [higer, auto]"),
+            Some("higher")
+        );
+        let min_level = |level: &str| {
+            Config::load(&DEFAULT_CONFIG.replace(
+                "min_bitrate_level:\n",
+                &format!("min_bitrate_level: {}\n", level),
+            ))
+        };
+        assert!(min_level("lossless").is_ok());
+        assert!(min_level("dolby").is_err());
+    }
+
+    #[test]
+    fn test_default_config_covers_all_fields() {
+        let config = ConfigLayers::default().build().unwrap();
+        let Value::Mapping(fields) = serde_yaml::to_value(&config).unwrap() else {
+            panic!();
+        };
+        let defaults: Mapping = serde_yaml::from_str(DEFAULT_CONFIG).unwrap();
+        assert_eq!(fields.len(), defaults.len());
+        assert!(fields.keys().all(|key| defaults.contains_key(key)));
+    }
+
     #[test]
     fn test() {
         let config =
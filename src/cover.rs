@@ -0,0 +1,342 @@
+//! 封面图片的格式识别、转换和尺寸读取
+use anyhow::{Context, Result, bail};
+use image::codecs::jpeg::JpegEncoder;
+use lofty::picture::MimeType;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::{Mutex, OnceCell};
+use url::Url;
+
+use crate::download::{DownloadOptions, fetch_bytes};
+
+/// 转换为JPEG时使用的质量
+const JPEG_QUALITY: u8 = 90;
+/// 封面的最大字节数
+const MAX_COVER_SIZE: u64 = 20 * 1024 * 1024;
+
+/// 通过文件头识别出的图片格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Jpeg,
+    Png,
+    Gif,
+    WebP,
+    Bmp,
+}
+
+impl ImageKind {
+    /// 根据文件头的魔数识别图片格式
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageKind::Jpeg)
+        } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageKind::Png)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Some(ImageKind::Gif)
+        } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(ImageKind::WebP)
+        } else if data.starts_with(b"BM") {
+            Some(ImageKind::Bmp)
+        } else {
+            None
+        }
+    }
+}
+
+/// 可以直接写入标签的封面
+#[derive(Debug, Clone)]
+pub struct Cover {
+    pub data: Vec<u8>,
+    pub mime_type: MimeType,
+    pub width: u32,
+    pub height: u32,
+    /// 每个像素的位数
+    pub color_depth: u32,
+}
+
+impl Cover {
+    /// JPEG和PNG直接使用，其它格式转换为JPEG
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let Some(kind) = ImageKind::sniff(&data) else {
+            bail!("Unknown image format");
+        };
+        let header = match kind {
+            ImageKind::Jpeg => jpeg_info(&data).map(|info| (MimeType::Jpeg, info)),
+            ImageKind::Png => png_info(&data).map(|info| (MimeType::Png, info)),
+            _ => None,
+        };
+        if let Some((mime_type, (width, height, color_depth))) = header {
+            return Ok(Self {
+                data,
+                mime_type,
+                width,
+                height,
+                color_depth,
+            });
+        }
+        let image = image::load_from_memory(&data)
+            .with_context(|| format!("Failed to decode {:?} image", kind))?
+            .to_rgb8();
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+            .encode_image(&image)
+            .context("Failed to encode image as JPEG")?;
+        Ok(Self {
+            data: jpeg,
+            mime_type: MimeType::Jpeg,
+            width: image.width(),
+            height: image.height(),
+            color_depth: 24,
+        })
+    }
+
+    /// 保存为图片文件时使用的扩展名
+    pub fn extension(&self) -> &'static str {
+        match self.mime_type {
+            MimeType::Png => "png",
+            _ => "jpg",
+        }
+    }
+}
+
+/// 缓存中的一张封面，下载完成前为空
+type CoverSlot = Arc<OnceCell<Option<Arc<Cover>>>>;
+
+/// 按链接缓存封面，同一张封面在一次任务中只下载一次
+pub struct CoverCache {
+    size: u32,
+    options: DownloadOptions,
+    covers: Mutex<HashMap<String, CoverSlot>>,
+}
+
+impl CoverCache {
+    /// `size` 为请求的封面边长，0为原图
+    pub fn new(size: u32, options: DownloadOptions) -> Self {
+        Self {
+            size,
+            options,
+            covers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 获取封面，下载或解析失败时返回 `None` 且不再重复下载
+    pub async fn get(&self, url: &str) -> Option<Arc<Cover>> {
+        let url = sized_url(url, self.size);
+        // 每个链接单独加锁，不同封面可以同时下载
+        let cell = self
+            .covers
+            .lock()
+            .await
+            .entry(url.clone())
+            .or_default()
+            .clone();
+        cell.get_or_init(|| async {
+            match self.fetch(&url).await {
+                Ok(v) => Some(Arc::new(v)),
+                Err(e) => {
+                    log::warn!("Failed to get cover {}: {:#}", url, e);
+                    None
+                }
+            }
+        })
+        .await
+        .clone()
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Cover> {
+        let url = Url::parse(url).context("Invalid cover url")?;
+        let data = fetch_bytes(&url, MAX_COVER_SIZE, self.options.clone()).await?;
+        Cover::from_bytes(data)
+    }
+}
+
+/// 保存到文件夹中的专辑封面和歌手图片，同一个文件夹在一次任务中只写入一次
+pub struct FolderImages {
+    overwrite: bool,
+    written: Mutex<HashSet<PathBuf>>,
+}
+
+impl FolderImages {
+    /// `overwrite` 为 `false` 时不覆盖已有的同名图片
+    pub fn new(overwrite: bool) -> Self {
+        Self {
+            overwrite,
+            written: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// 是否需要在文件夹中写入名为 `name` 的图片，返回 `true` 后同一路径不会再次返回 `true`
+    pub async fn claim(&self, dir: &Path, name: &str) -> bool {
+        if !self.written.lock().await.insert(dir.join(name)) {
+            return false;
+        }
+        self.overwrite || !image_exists(dir, name)
+    }
+
+    /// 把图片保存为 `dir/name.jpg` 或 `dir/name.png`，并删除扩展名不同的旧图片
+    pub async fn save(&self, dir: &Path, name: &str, image: &Cover) -> Result<PathBuf> {
+        let path = dir.join(format!("{}.{}", name, image.extension()));
+        tokio::fs::write(&path, &image.data)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        for ext in IMAGE_EXTENSIONS {
+            let old = dir.join(format!("{}.{}", name, ext));
+            if old != path && old.is_file() {
+                let _ = tokio::fs::remove_file(old).await;
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// 文件夹图片可能使用的扩展名
+const IMAGE_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+fn image_exists(dir: &Path, name: &str) -> bool {
+    IMAGE_EXTENSIONS
+        .iter()
+        .any(|ext| dir.join(format!("{}.{}", name, ext)).is_file())
+}
+
+/// 通过CDN的 `param` 参数请求指定边长的封面，`size` 为0时使用原图
+pub fn sized_url(url: &str, size: u32) -> String {
+    if size == 0 {
+        return url.to_string();
+    }
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+    let pairs: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(key, _)| key != "param")
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    parsed
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("param", &format!("{}y{}", size, size));
+    parsed.to_string()
+}
+
+/// 读取JPEG的宽、高和位深
+fn jpeg_info(data: &[u8]) -> Option<(u32, u32, u32)> {
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        if data[i] != 0xFF {
+            return None;
+        }
+        let marker = data[i + 1];
+        // 填充字节和没有长度的标记
+        if marker == 0xFF {
+            i += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD9).contains(&marker) {
+            i += 2;
+            continue;
+        }
+        let length = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+        let is_sof = (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker);
+        if is_sof {
+            let segment = data.get(i + 4..i + 10)?;
+            let precision = segment[0] as u32;
+            let height = u16::from_be_bytes([segment[1], segment[2]]) as u32;
+            let width = u16::from_be_bytes([segment[3], segment[4]]) as u32;
+            let components = segment[5] as u32;
+            return Some((width, height, precision * components));
+        }
+        i += 2 + length;
+    }
+    None
+}
+
+/// 读取PNG的宽、高和位深
+fn png_info(data: &[u8]) -> Option<(u32, u32, u32)> {
+    if data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+    let bit_depth = *data.get(24)? as u32;
+    let channels = match data.get(25)? {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return None,
+    };
+    Some((width, height, bit_depth * channels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let image = RgbImage::new(3, 2);
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn test_cover_from_bytes() {
+        let png = Cover::from_bytes(encode(ImageFormat::Png)).unwrap();
+        assert_eq!(png.mime_type, MimeType::Png);
+        assert_eq!((png.width, png.height, png.color_depth), (3, 2, 24));
+
+        let jpeg = Cover::from_bytes(encode(ImageFormat::Jpeg)).unwrap();
+        assert_eq!(jpeg.mime_type, MimeType::Jpeg);
+        assert_eq!((jpeg.width, jpeg.height, jpeg.color_depth), (3, 2, 24));
+
+        let bmp = encode(ImageFormat::Bmp);
+        assert_eq!(ImageKind::sniff(&bmp), Some(ImageKind::Bmp));
+        let converted = Cover::from_bytes(bmp).unwrap();
+        assert_eq!(converted.mime_type, MimeType::Jpeg);
+        assert_eq!(ImageKind::sniff(&converted.data), Some(ImageKind::Jpeg));
+        assert_eq!((converted.width, converted.height), (3, 2));
+
+        assert!(Cover::from_bytes(b"<html>".to_vec()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_folder_images() {
+        let dir = std::env::temp_dir().join("ncmd-folder-images");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("cover.jpeg"), b"old").unwrap();
+        let png = Cover::from_bytes(encode(ImageFormat::Png)).unwrap();
+
+        let images = FolderImages::new(false);
+        assert!(!images.claim(&dir, "cover").await);
+        assert!(images.claim(&dir, "artist").await);
+        assert!(!images.claim(&dir, "artist").await);
+
+        let images = FolderImages::new(true);
+        assert!(images.claim(&dir, "cover").await);
+        let path = images.save(&dir, "cover", &png).await.unwrap();
+        assert_eq!(path, dir.join("cover.png"));
+        assert!(!dir.join("cover.jpeg").exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_sized_url() {
+        let url = "https://p1.music.126.net/abc/123.jpg";
+        assert_eq!(sized_url(url, 0), url);
+        assert_eq!(
+            sized_url(url, 1400),
+            "https://p1.music.126.net/abc/123.jpg?param=1400y1400"
+        );
+        assert_eq!(
+            sized_url(&format!("{}?param=140y140", url), 500),
+            "https://p1.music.126.net/abc/123.jpg?param=500y500"
+        );
+    }
+}
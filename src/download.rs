@@ -9,6 +9,7 @@ use url::Url;
 
 const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36";
 const CHUNK_SIZE: usize = 8192;
+#[derive(Debug, Clone)]
 pub struct DownloadOptions {
     /// Maximum number of retry attempts
     pub max_retries: usize,
@@ -49,18 +50,46 @@ impl Default for DownloadOptions {
 /// * `Result<u64>` - 下载的字节数
 #[allow(unused)]
 pub async fn download_file(url: &Url, output_path: &Path, options: DownloadOptions) -> Result<u64> {
-    let client = ClientBuilder::new()
+    let client = build_client(&options)?;
+    with_retry(&options, || {
+        download_with_client(&client, url, output_path, CHUNK_SIZE)
+    })
+    .await
+}
+
+/// 异步下载到内存，适合封面等小文件，支持自动重试和超时
+///
+/// # Arguments
+/// * `url` - 下载链接
+/// * `max_size` - 允许的最大字节数，超过时视为下载失败
+/// * `options` - 下载配置选项
+///
+/// # Returns
+/// * `Result<Vec<u8>>` - 下载的内容
+pub async fn fetch_bytes(url: &Url, max_size: u64, options: DownloadOptions) -> Result<Vec<u8>> {
+    let client = build_client(&options)?;
+    with_retry(&options, || fetch_with_client(&client, url, max_size)).await
+}
+
+fn build_client(options: &DownloadOptions) -> Result<Client> {
+    ClientBuilder::new()
         .timeout(options.timeout)
         .user_agent(USER_AGENT)
         .build()
-        .context("Failed to create HTTP client")?;
+        .context("Failed to create HTTP client")
+}
 
+async fn with_retry<T, F, Fut>(options: &DownloadOptions, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
     let mut retries = 0usize;
-    let mut current_delay = options.retry_delay;
+    let current_delay = options.retry_delay;
 
     loop {
-        match download_with_client(&client, url, output_path, CHUNK_SIZE).await {
-            Ok(bytes) => return Ok(bytes),
+        match f().await {
+            Ok(v) => return Ok(v),
             Err(e) if retries < options.max_retries => {
                 retries += 1;
                 log::warn!(
@@ -81,6 +110,36 @@ pub async fn download_file(url: &Url, output_path: &Path, options: DownloadOptio
     }
 }
 
+async fn fetch_with_client(client: &Client, url: &Url, max_size: u64) -> Result<Vec<u8>> {
+    let mut response = client
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("Failed to send request to {}", url))?;
+
+    if !response.status().is_success() {
+        bail!("HTTP error: {}", response.status());
+    }
+    if let Some(total) = response.content_length()
+        && total > max_size
+    {
+        bail!("Response too large: {} bytes (limit {})", total, max_size);
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .with_context(|| "Failed to read response body")?
+    {
+        if data.len() as u64 + chunk.len() as u64 > max_size {
+            bail!("Response too large: more than {} bytes", max_size);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
 async fn download_with_client(
     client: &Client,
     url: &Url,
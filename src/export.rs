@@ -0,0 +1,175 @@
+//! 把歌单中歌曲的信息导出为JSON、CSV或 `歌手 - 标题` 格式的文本
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write, path::Path};
+
+use crate::track::Track;
+
+/// 导出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    /// 每行一首 `歌手 - 标题`，可用于其它音乐平台的歌单导入工具
+    Txt,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Txt => "txt",
+        }
+    }
+}
+
+/// 导出的一首歌曲
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    /// 在歌单中的位置，从1开始
+    pub index: usize,
+    pub id: u64,
+    pub title: String,
+    pub translated_title: Option<String>,
+    pub artists: Vec<String>,
+    pub album: String,
+    pub album_id: u64,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    /// 发行日期，格式为 `YYYY-MM-DD`
+    pub release_date: Option<String>,
+    pub genre: Option<String>,
+    /// 时长(毫秒)
+    pub duration: u64,
+    /// 添加到歌单的时间，格式为 `YYYY-MM-DD HH:MM:SS`
+    pub added_at: Option<String>,
+}
+
+impl Record {
+    pub fn new(track: &Track) -> Self {
+        Self {
+            index: track.playlist_index,
+            id: track.id,
+            title: track.title.clone(),
+            translated_title: track.translated_title.clone(),
+            artists: track.artists.clone(),
+            album: track.album.clone(),
+            album_id: track.album_id,
+            album_artist: track.album_artist.clone(),
+            track_number: track.track_number,
+            disc_number: track.disc_number,
+            release_date: track.release_date_string(),
+            genre: track.genre.clone(),
+            duration: track.duration,
+            added_at: None,
+        }
+    }
+}
+
+/// 导出到文件，已有的文件会被覆盖
+pub fn write(path: &Path, format: ExportFormat, records: &[Record]) -> Result<()> {
+    let content = match format {
+        ExportFormat::Json => serde_json::to_string_pretty(records)?,
+        ExportFormat::Csv => to_csv(records),
+        ExportFormat::Txt => to_text(records),
+    };
+    std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn to_csv(records: &[Record]) -> String {
+    // 带BOM以便Excel识别UTF-8
+    let mut content = String::from(
+        "\u{feff}index,id,title,translated_title,artists,album,album_id,album_artist,track_number,disc_number,release_date,genre,duration,added_at\r\n",
+    );
+    let optional = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_default();
+    for record in records {
+        let fields = [
+            record.index.to_string(),
+            record.id.to_string(),
+            record.title.clone(),
+            record.translated_title.clone().unwrap_or_default(),
+            record.artists.join("; "),
+            record.album.clone(),
+            record.album_id.to_string(),
+            record.album_artist.clone().unwrap_or_default(),
+            optional(record.track_number),
+            optional(record.disc_number),
+            record.release_date.clone().unwrap_or_default(),
+            record.genre.clone().unwrap_or_default(),
+            record.duration.to_string(),
+            record.added_at.clone().unwrap_or_default(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|v| escape_csv(v)).collect();
+        let _ = write!(content, "{}\r\n", fields.join(","));
+    }
+    content
+}
+
+/// 每行一首 `歌手 - 标题`，多位歌手用逗号分隔，没有歌手时只写标题
+fn to_text(records: &[Record]) -> String {
+    let mut content = String::new();
+    for record in records {
+        let artists: Vec<&str> = record
+            .artists
+            .iter()
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .collect();
+        let title = record.title.trim();
+        if artists.is_empty() {
+            let _ = writeln!(content, "{}", title);
+        } else {
+            let _ = writeln!(content, "{} - {}", artists.join(", "), title);
+        }
+    }
+    content
+}
+
+/// 包含逗号、引号或换行时用引号括起来，引号写两次
+fn escape_csv(s: &str) -> String {
+    if s.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv() {
+        let track = Track {
+            id: 42,
+            title: "Say \"Hi\", Bye".to_string(),
+            artists: vec!["A".to_string(), "B".to_string()],
+            album: "Album".to_string(),
+            track_number: Some(3),
+            duration: 1000,
+            playlist_index: 1,
+            ..Default::default()
+        };
+        let mut record = Record::new(&track);
+        record.added_at = Some("2024-01-02 03:04:05".to_string());
+        let csv = to_csv(&[record.clone()]);
+        assert!(csv.ends_with(
+            "\r\n1,42,\"Say \"\"Hi\"\", Bye\",,A; B,Album,0,,3,,,,1000,2024-01-02 03:04:05\r\n"
+        ));
+        let json = serde_json::to_value([&record]).unwrap();
+        assert_eq!(json[0]["artists"][1], "B");
+        assert_eq!(json[0]["added_at"], "2024-01-02 03:04:05");
+        record.artists.push(" ".to_string());
+        let untitled = Record {
+            artists: Vec::new(),
+            ..record.clone()
+        };
+        assert_eq!(
+            to_text(&[record, untitled]),
+            "A, B - Say \"Hi\", Bye\nSay \"Hi\", Bye\n"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! 逐字歌词(yrc)的解析，以及导出为增强LRC和ASS字幕
+//!
+//! yrc的每一行形如 `[行开始,行时长](字开始,字时长,0)字(字开始,字时长,0)字`，时间单位为毫秒
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use crate::lyrics::format_timestamp;
+
+/// ASS字幕的文件头(标题之后的部分)，使用1080p画布和底部居中的默认样式
+const ASS_HEADER: &str = "ScriptType: v4.00+
+PlayResX: 1920
+PlayResY: 1080
+WrapStyle: 2
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Default,Arial,64,&H00FFFFFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,3,0,2,40,40,80,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+";
+
+/// 逐字歌词的导出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KaraokeFormat {
+    /// 带 `<mm:ss.xx>` 逐字时间的增强LRC
+    Lrc,
+    /// 使用 `\k` 卡拉OK效果的ASS字幕
+    Ass,
+}
+
+impl KaraokeFormat {
+    /// 保存时使用的扩展名，增强LRC与普通歌词区分开
+    pub fn extension(&self) -> &'static str {
+        match self {
+            KaraokeFormat::Lrc => "karaoke.lrc",
+            KaraokeFormat::Ass => "ass",
+        }
+    }
+
+    pub fn export(&self, lines: &[KaraokeLine], title: &str) -> String {
+        match self {
+            KaraokeFormat::Lrc => to_enhanced_lrc(lines),
+            KaraokeFormat::Ass => to_ass(lines, title),
+        }
+    }
+}
+
+/// 一个字(或词)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    /// 开始时间(毫秒)
+    pub start: u32,
+    /// 时长(毫秒)
+    pub duration: u32,
+    pub text: String,
+}
+
+/// 逐字歌词的一行
+#[derive(Debug, Clone, PartialEq)]
+pub struct KaraokeLine {
+    /// 开始时间(毫秒)
+    pub start: u32,
+    /// 时长(毫秒)
+    pub duration: u32,
+    pub words: Vec<Word>,
+}
+
+impl KaraokeLine {
+    fn end(&self) -> u32 {
+        let words_end = self.words.last().map(|v| v.start + v.duration);
+        (self.start + self.duration).max(words_end.unwrap_or_default())
+    }
+}
+
+/// 解析yrc歌词，忽略开头JSON格式的署名行和无法解析的行
+pub fn parse_yrc(yrc: &str) -> Vec<KaraokeLine> {
+    let mut lines: Vec<KaraokeLine> = yrc
+        .lines()
+        .filter_map(|line| {
+            let (timing, rest) = line.trim().strip_prefix('[')?.split_once(']')?;
+            let (start, duration) = parse_timing(timing)?;
+            let words = parse_words(rest);
+            (!words.is_empty()).then_some(KaraokeLine {
+                start,
+                duration,
+                words,
+            })
+        })
+        .collect();
+    lines.sort_by_key(|v| v.start);
+    lines
+}
+
+/// 导出为增强LRC，例如 `[00:01.00]<00:01.00>字<00:01.50>字<00:02.00>`
+pub fn to_enhanced_lrc(lines: &[KaraokeLine]) -> String {
+    let mut content = String::new();
+    for line in lines {
+        let _ = write!(content, "[{}]", format_timestamp(line.start));
+        for word in &line.words {
+            let _ = write!(content, "<{}>{}", format_timestamp(word.start), word.text);
+        }
+        let _ = writeln!(content, "<{}>", format_timestamp(line.end()));
+    }
+    content
+}
+
+/// 导出为ASS字幕，每行一条字幕，每个字用 `\k` 标记时长(厘秒)
+pub fn to_ass(lines: &[KaraokeLine], title: &str) -> String {
+    let mut content = format!(
+        "[Script Info]\nTitle: {}\n{}",
+        escape_ass(title),
+        ASS_HEADER
+    );
+    for line in lines {
+        let mut text = String::new();
+        // 以相对于行开始的厘秒计算，避免逐字取整累积误差
+        let centis = |time: u32| (time.saturating_sub(line.start) + 5) / 10;
+        let mut position = 0;
+        for word in &line.words {
+            let start = centis(word.start);
+            if start > position {
+                let _ = write!(text, "{{\\k{}}}", start - position);
+                position = start;
+            }
+            let end = centis(word.start + word.duration).max(position);
+            let _ = write!(text, "{{\\k{}}}{}", end - position, escape_ass(&word.text));
+            position = end;
+        }
+        let _ = writeln!(
+            content,
+            "Dialogue: 0,{},{},Default,,0,0,0,,{}",
+            ass_time(line.start),
+            ass_time(line.end()),
+            text
+        );
+    }
+    content
+}
+
+/// 解析 `开始,时长` 或 `开始,时长,0`
+fn parse_timing(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.split(',');
+    let start = parts.next()?.trim().parse().ok()?;
+    let duration = parts.next()?.trim().parse().ok()?;
+    Some((start, duration))
+}
+
+/// 取出开头的 `(开始,时长,0)`，返回时间和剩余的内容
+fn split_word_timing(s: &str) -> Option<((u32, u32), &str)> {
+    let (timing, rest) = s.strip_prefix('(')?.split_once(')')?;
+    Some((parse_timing(timing)?, rest))
+}
+
+fn parse_words(mut rest: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    while let Some(((start, duration), after)) = split_word_timing(rest) {
+        // 歌词本身也可能包含括号，只在能解析为时间的括号处分割
+        let end = after
+            .match_indices('(')
+            .map(|(i, _)| i)
+            .find(|&i| split_word_timing(&after[i..]).is_some())
+            .unwrap_or(after.len());
+        words.push(Word {
+            start,
+            duration,
+            text: after[..end].to_string(),
+        });
+        rest = &after[end..];
+    }
+    words
+}
+
+/// 格式化为ASS使用的 `h:mm:ss.cc`
+fn ass_time(millis: u32) -> String {
+    let centis = (millis + 5) / 10;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360_000,
+        centis / 6000 % 60,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+/// 花括号会被当作样式标记，反斜杠会被当作转义
+fn escape_ass(s: &str) -> String {
+    s.replace('\\', "＼")
+        .replace('{', "｛")
+        .replace('}', "｝")
+        .replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YRC: &str = "{\"t\":0,\"c\":[{\"tx\":\"作词: \"}]}\n\
+        [1000,1500](1000,500,0)Hel(1500,300,0)lo (2000,500,0)(Live)\n\
+        [500,400](500,400,0)前";
+
+    #[test]
+    fn test_parse_yrc() {
+        let lines = parse_yrc(YRC);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].start, 500);
+        let texts: Vec<&str> = lines[1].words.iter().map(|v| v.text.as_str()).collect();
+        assert_eq!(texts, ["Hel", "lo ", "(Live)"]);
+        assert_eq!(lines[1].words[2].start, 2000);
+    }
+
+    #[test]
+    fn test_export() {
+        let lines = parse_yrc(YRC);
+        assert_eq!(
+            to_enhanced_lrc(&lines[1..]),
+            "[00:01.00]<00:01.00>Hel<00:01.50>lo <00:02.00>(Live)<00:02.50>\n"
+        );
+        let ass = to_ass(&lines[1..], "Song {1}");
+        assert!(ass.contains("Title: Song ｛1｝\n"));
+        assert!(ass.ends_with(
+            "Dialogue: 0,0:00:01.00,0:00:02.50,Default,,0,0,0,,{\\k50}Hel{\\k30}lo {\\k20}{\\k50}(Live)\n"
+        ));
+    }
+}
@@ -0,0 +1,92 @@
+//! 输出目录中已有的歌曲文件
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{metadata, playlist::AUDIO_EXTENSIONS};
+
+/// 按标签中记录的歌曲Id索引的歌曲文件
+#[derive(Debug, Default)]
+pub struct Library {
+    /// 歌曲Id => 相对于输出目录的路径
+    files: HashMap<u64, PathBuf>,
+}
+
+impl Library {
+    /// 扫描文件夹中所有歌曲文件的标签，同一首歌曲有多个文件时使用路径排序靠前的
+    ///
+    /// 会读取大量文件，在异步任务中应通过 `spawn_blocking` 调用
+    pub fn scan(dir: &Path) -> Self {
+        let mut files = HashMap::new();
+        for relative in find_audio_files(dir) {
+            if let Some(id) = metadata::read_song_id(&dir.join(&relative)) {
+                files.entry(id).or_insert(relative);
+            }
+        }
+        Self { files }
+    }
+
+    pub fn find(&self, id: u64) -> Option<&Path> {
+        self.files.get(&id).map(PathBuf::as_path)
+    }
+}
+
+/// 递归查找文件夹中的歌曲文件，返回按路径排序的相对路径
+///
+/// 不进入符号链接指向的文件夹，避免循环链接导致无限递归
+pub fn find_audio_files(dir: &Path) -> Vec<PathBuf> {
+    fn walk(root: &Path, dir: &Path, result: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                walk(root, &path, result);
+            } else if path.is_file()
+                && path
+                    .extension()
+                    .map(|v| v.to_string_lossy().to_lowercase())
+                    .is_some_and(|v| AUDIO_EXTENSIONS.contains(&v.as_str()))
+                && let Ok(relative) = path.strip_prefix(root)
+            {
+                result.push(relative.to_path_buf());
+            }
+        }
+    }
+    let mut result = Vec::new();
+    walk(dir, dir, &mut result);
+    result.sort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_audio_files() {
+        let dir = std::env::temp_dir().join("ncmd-library");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("List/Album")).unwrap();
+        for name in ["List/b.FLAC", "List/Album/a.mp3", "List/a.lrc", "c.m4a"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        // 指向上级文件夹的循环链接
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("List/loop")).unwrap();
+        assert_eq!(
+            find_audio_files(&dir),
+            [
+                PathBuf::from("List/Album/a.mp3"),
+                PathBuf::from("List/b.FLAC"),
+                PathBuf::from("c.m4a")
+            ]
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,538 @@
+//! LRC歌词的解析、规范化和格式转换，以及翻译、罗马音歌词的合并
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, fmt::Write};
+
+use crate::api::SongLyrics;
+
+/// 合并歌词时，时间相差不超过该值(毫秒)的行视为同一行
+const MERGE_TOLERANCE: u32 = 100;
+
+/// SRT中最后一行歌词的显示时长(毫秒)
+const LAST_LINE_DURATION: u32 = 5000;
+
+/// 署名行冒号前的名称，用空格分隔，例如 `作词 : xxx`，比较时忽略空白、大小写和结尾的 `by`
+const CREDIT_KEYS: &str = "作词 作曲 编曲 词 曲 制作 制作人 监制 混音 混音师 母带 录音 录音师 \
+    和声 和音 吉他 贝斯 鼓 弦乐 钢琴 出品 出品人 发行 企划 统筹 op sp lyrics lyricist written \
+    music composer composed arranger arranged producer produced mix mixed mastered recorded vocal";
+
+/// 翻译、罗马音等附加歌词
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricVariant {
+    Translation,
+    Romaji,
+}
+
+impl LyricVariant {
+    /// 单独保存时文件名中的后缀，例如 `歌名.translation.lrc`
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            LyricVariant::Translation => "translation",
+            LyricVariant::Romaji => "romaji",
+        }
+    }
+
+    fn text<'a>(&self, lyrics: &'a SongLyrics) -> &'a str {
+        match self {
+            LyricVariant::Translation => &lyrics.translation,
+            LyricVariant::Romaji => &lyrics.romaji,
+        }
+    }
+}
+
+/// 附加歌词的保存方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricsStyle {
+    /// 保存为单独的文件
+    #[default]
+    Separate,
+    /// 在原文的下一行以相同的时间写入
+    Interleave,
+    /// 与原文写在同一行，以 ` / ` 分隔
+    Inline,
+}
+
+/// 署名行(作词、作曲等)的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CreditLines {
+    /// 保留，开头JSON格式的署名转换为普通歌词行
+    #[default]
+    Keep,
+    /// 删除
+    Strip,
+}
+
+/// 歌词文件的格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricsFormat {
+    #[default]
+    Lrc,
+    Srt,
+    /// 不带时间的纯文本
+    Txt,
+}
+
+impl LyricsFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LyricsFormat::Lrc => "lrc",
+            LyricsFormat::Srt => "srt",
+            LyricsFormat::Txt => "txt",
+        }
+    }
+
+    pub fn serialize(&self, lrc: &Lrc) -> String {
+        match self {
+            LyricsFormat::Lrc => lrc.to_lrc(),
+            LyricsFormat::Srt => lrc.to_srt(),
+            LyricsFormat::Txt => lrc.to_plain(),
+        }
+    }
+}
+
+/// 带时间的一行歌词
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncedLine {
+    /// 开始时间(毫秒)
+    pub time: u32,
+    pub text: String,
+}
+
+/// 解析后的LRC歌词
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lrc {
+    /// `[ar:歌手]` 等信息标签，同名的标签只保留第一个，不含 `offset`
+    pub tags: Vec<(String, String)>,
+    /// 带时间的歌词行，按时间排序，已经应用了 `[offset:]`
+    pub lines: Vec<SyncedLine>,
+    /// 没有时间的歌词行，例如只有纯文本的歌词
+    pub untimed: Vec<String>,
+}
+
+impl Lrc {
+    /// 解析LRC歌词，一行有多个时间标签时拆成多行，同时兼容CRLF换行
+    ///
+    /// 只有第一句歌词之前的行才会被当作署名
+    pub fn parse(content: &str, credits: CreditLines) -> Self {
+        let mut lrc = Self::default();
+        let mut offset = 0i64;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some((time, text)) = parse_json_credit(line) {
+                if credits == CreditLines::Keep {
+                    lrc.lines.push(SyncedLine { time, text });
+                }
+                continue;
+            }
+            let (times, text) = split_timestamps(line);
+            let text = text.trim();
+            if !times.is_empty() {
+                lrc.lines.extend(times.into_iter().map(|time| SyncedLine {
+                    time,
+                    text: text.to_string(),
+                }));
+            } else if let Some((key, value)) = parse_tag(line) {
+                if key == "offset" {
+                    offset = value.trim().parse().unwrap_or_default();
+                } else if !lrc.tags.iter().any(|(k, _)| *k == key) {
+                    lrc.tags.push((key, value.trim().to_string()));
+                }
+            } else {
+                lrc.untimed.push(line.to_string());
+            }
+        }
+        // offset为正时歌词提前显示
+        for line in &mut lrc.lines {
+            line.time = (line.time as i64 - offset).max(0) as u32;
+        }
+        lrc.lines.sort_by_key(|v| v.time);
+        if credits == CreditLines::Strip {
+            let mut in_credits = true;
+            lrc.lines
+                .retain(|v| !is_leading_credit(&v.text, &mut in_credits));
+            let mut in_credits = true;
+            lrc.untimed
+                .retain(|v| !is_leading_credit(v, &mut in_credits));
+        }
+        lrc
+    }
+
+    /// 时间统一为 `[mm:ss.xx]`，每行一个时间标签
+    pub fn to_lrc(&self) -> String {
+        let mut content = String::new();
+        for (key, value) in &self.tags {
+            let _ = writeln!(content, "[{}:{}]", key, value);
+        }
+        for line in &self.untimed {
+            let _ = writeln!(content, "{}", line);
+        }
+        for line in &self.lines {
+            let _ = writeln!(content, "[{}]{}", format_timestamp(line.time), line.text);
+        }
+        content
+    }
+
+    /// 每行显示到下一行开始，空行只用于结束上一行，没有时间的歌词行会被忽略
+    pub fn to_srt(&self) -> String {
+        let mut content = String::new();
+        let mut index = 0;
+        for (i, line) in self.lines.iter().enumerate() {
+            if line.text.is_empty() {
+                continue;
+            }
+            let end = self.lines[i + 1..]
+                .iter()
+                .map(|v| v.time)
+                .find(|&v| v > line.time)
+                .unwrap_or(line.time + LAST_LINE_DURATION);
+            index += 1;
+            let _ = writeln!(
+                content,
+                "{}\n{} --> {}\n{}\n",
+                index,
+                srt_time(line.time),
+                srt_time(end),
+                line.text
+            );
+        }
+        content
+    }
+
+    /// 不带时间的纯文本，连续的空行合并为一行
+    pub fn to_plain(&self) -> String {
+        let texts: Vec<&str> = if self.lines.is_empty() {
+            self.untimed.iter().map(|v| v.as_str()).collect()
+        } else {
+            self.lines.iter().map(|v| v.text.as_str()).collect()
+        };
+        let mut result: Vec<&str> = Vec::new();
+        for text in texts {
+            if text.is_empty() && result.last().is_none_or(|v| v.is_empty()) {
+                continue;
+            }
+            result.push(text);
+        }
+        while result.last().is_some_and(|v| v.is_empty()) {
+            result.pop();
+        }
+        let mut content = result.join("\n");
+        content.push('\n');
+        content
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.iter().all(|v| v.text.is_empty()) && self.untimed.is_empty()
+    }
+}
+
+/// 解析LRC歌词中带时间标签的行，一行有多个时间标签时拆成多行，按时间排序
+pub fn parse_synced(lrc: &str) -> Vec<SyncedLine> {
+    Lrc::parse(lrc, CreditLines::Keep).lines
+}
+
+/// 按配置的方式组合歌词，返回原文(或合并后)的歌词和需要单独保存的附加歌词
+///
+/// 没有内容的附加歌词会被忽略
+pub fn compose(
+    lyrics: &SongLyrics,
+    variants: &[LyricVariant],
+    style: LyricsStyle,
+) -> (String, Vec<(LyricVariant, String)>) {
+    let extras: Vec<(LyricVariant, &str)> = variants
+        .iter()
+        .map(|&v| (v, v.text(lyrics)))
+        .filter(|(_, text)| !text.trim().is_empty())
+        .collect();
+    if style == LyricsStyle::Separate {
+        let extras = extras
+            .into_iter()
+            .map(|(v, text)| (v, text.to_string()))
+            .collect();
+        return (lyrics.lyric.clone(), extras);
+    }
+    let texts: Vec<&str> = extras.iter().map(|(_, text)| *text).collect();
+    (merge(&lyrics.lyric, &texts, style), Vec::new())
+}
+
+/// 把附加歌词按时间合并到原文中，原文中没有时间的行和没有对应附加歌词的行保持不变
+fn merge(original: &str, extras: &[&str], style: LyricsStyle) -> String {
+    let extras: Vec<Vec<SyncedLine>> = extras.iter().map(|v| parse_synced(v)).collect();
+    let mut result = Vec::new();
+    for line in original.lines() {
+        let trimmed = line.trim();
+        let (times, text) = split_timestamps(trimmed);
+        let prefix = &trimmed[..trimmed.len() - text.len()];
+        let text = text.trim();
+        let matched: Vec<&str> = match times.first() {
+            Some(&time) if !text.is_empty() => extras
+                .iter()
+                .filter_map(|lines| find_line(lines, time))
+                // 网易云音乐用 `//` 表示该行没有翻译
+                .filter(|v| !v.is_empty() && *v != "//" && *v != text)
+                .collect(),
+            _ => Vec::new(),
+        };
+        if matched.is_empty() {
+            result.push(line.to_string());
+            continue;
+        }
+        if style == LyricsStyle::Inline {
+            let texts: Vec<&str> = std::iter::once(text).chain(matched).collect();
+            result.push(format!("{}{}", prefix, texts.join(" / ")));
+        } else {
+            result.push(format!("{}{}", prefix, text));
+            for v in matched {
+                result.push(format!("{}{}", prefix, v));
+            }
+        }
+    }
+    result.join("\n")
+}
+
+/// 时间最接近的一行
+fn find_line(lines: &[SyncedLine], time: u32) -> Option<&str> {
+    lines
+        .iter()
+        .filter(|v| v.time.abs_diff(time) <= MERGE_TOLERANCE)
+        .min_by_key(|v| v.time.abs_diff(time))
+        .map(|v| v.text.as_str())
+}
+
+/// 去掉时间标签和 `[ar:xxx]` 等信息标签后的纯文本歌词
+pub fn plain_text(lrc: &str) -> String {
+    let mut result: Vec<&str> = Vec::new();
+    for line in lrc.lines() {
+        let text = strip_tags(line);
+        if text.is_empty() && result.last().is_none_or(|v| v.is_empty()) {
+            continue;
+        }
+        result.push(text);
+    }
+    while result.last().is_some_and(|v| v.is_empty()) {
+        result.pop();
+    }
+    result.join("\n")
+}
+
+/// 去掉行首的所有 `[...]` 标签
+pub fn strip_tags(line: &str) -> &str {
+    let mut line = line.trim();
+    while line.starts_with('[')
+        && let Some(end) = line.find(']')
+    {
+        line = line[end + 1..].trim_start();
+    }
+    line
+}
+
+/// 歌词行去掉标签后的文本，JSON格式的署名转换为 `作词: 某人` 的形式
+pub fn line_text(line: &str) -> Cow<'_, str> {
+    match parse_json_credit(line.trim()) {
+        Some((_, text)) => Cow::Owned(text),
+        None => Cow::Borrowed(strip_tags(line)),
+    }
+}
+
+/// 解析 `[ar:歌手]` 形式的信息标签，键统一为小写
+fn parse_tag(line: &str) -> Option<(String, &str)> {
+    let (key, value) = line.strip_prefix('[')?.strip_suffix(']')?.split_once(':')?;
+    let key = key.trim();
+    (!key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic()))
+        .then(|| (key.to_ascii_lowercase(), value))
+}
+
+/// 网易云音乐歌词开头的JSON格式署名，例如 `{"t":0,"c":[{"tx":"作词: "},{"tx":"某人"}]}`
+fn parse_json_credit(line: &str) -> Option<(u32, String)> {
+    if !line.starts_with('{') {
+        return None;
+    }
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    let time = value.get("t")?.as_u64()? as u32;
+    let text: String = value
+        .get("c")?
+        .as_array()?
+        .iter()
+        .filter_map(|v| v.get("tx")?.as_str())
+        .collect();
+    Some((time, text.trim().to_string()))
+}
+
+/// 是否为 `作词 : xxx` 形式的署名行
+fn is_credit(text: &str) -> bool {
+    let Some((key, value)) = text.split_once([':', '：']) else {
+        return false;
+    };
+    let key: String = key
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let key = key.strip_suffix("by").unwrap_or(&key);
+    !value.trim().is_empty() && CREDIT_KEYS.split_whitespace().any(|v| v == key)
+}
+
+/// 是否为第一句歌词之前的署名行，遇到第一句歌词后将 `in_credits` 置为 `false`
+fn is_leading_credit(text: &str, in_credits: &mut bool) -> bool {
+    if !*in_credits {
+        return false;
+    }
+    if is_credit(text) {
+        return true;
+    }
+    *in_credits = text.is_empty();
+    false
+}
+
+/// 取出行首的时间标签，返回各时间(毫秒)和剩余的文本
+fn split_timestamps(line: &str) -> (Vec<u32>, &str) {
+    let mut times = Vec::new();
+    let mut rest = line.trim();
+    while rest.starts_with('[')
+        && let Some(end) = rest.find(']')
+        && let Some(time) = parse_timestamp(&rest[1..end])
+    {
+        times.push(time);
+        rest = &rest[end + 1..];
+    }
+    (times, rest)
+}
+
+/// 格式化为LRC使用的 `mm:ss.xx`
+pub fn format_timestamp(millis: u32) -> String {
+    let centis = (millis + 5) / 10;
+    format!(
+        "{:02}:{:02}.{:02}",
+        centis / 6000,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+/// 格式化为SRT使用的 `hh:mm:ss,mmm`
+fn srt_time(millis: u32) -> String {
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// 解析 `mm:ss`、`mm:ss.xx`、`mm:ss.xxx` 格式的时间，返回毫秒
+pub fn parse_timestamp(s: &str) -> Option<u32> {
+    let (minutes, seconds) = s.trim().split_once(':')?;
+    let minutes: u32 = minutes.parse().ok()?;
+    let (seconds, fraction) = match seconds.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (seconds, ""),
+    };
+    let seconds: u32 = seconds.parse().ok()?;
+    if seconds >= 60 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let millis = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<u32>().ok()? * 100,
+        2 => fraction.parse::<u32>().ok()? * 10,
+        _ => fraction[..3].parse::<u32>().ok()?,
+    };
+    Some((minutes * 60 + seconds) * 1000 + millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LRC: &str = "[ar:歌手]\n[00:01.50]第一句\n[00:10.123][00:03.00]第二句\n\n[00:20]";
+
+    #[test]
+    fn test_parse_synced() {
+        let lines = parse_synced(LRC);
+        let times: Vec<u32> = lines.iter().map(|v| v.time).collect();
+        assert_eq!(times, vec![1500, 3000, 10123, 20000]);
+        assert_eq!(lines[1].text, "第二句");
+        assert_eq!(lines[3].text, "");
+    }
+
+    #[test]
+    fn test_lrc_model() {
+        let content = "{\"t\":0,\"c\":[{\"tx\":\"作词: \"},{\"tx\":\"某人\"}]}\r\n\
+            [ti:歌]\r\n[TI:重复]\r\n[offset:500]\r\n\
+            [00:02.5]第二句\r\n[00:01.123]第一句\r\n[00:00.50]作曲 : 某人\r\n[00:04.00]";
+        let lrc = Lrc::parse(content, CreditLines::Keep);
+        assert_eq!(lrc.tags, [("ti".to_string(), "歌".to_string())]);
+        let times: Vec<u32> = lrc.lines.iter().map(|v| v.time).collect();
+        assert_eq!(times, [0, 0, 623, 2000, 3500]);
+        assert_eq!(lrc.lines[0].text, "作词: 某人");
+
+        let lrc = Lrc::parse(content, CreditLines::Strip);
+        assert_eq!(
+            lrc.to_lrc(),
+            "[ti:歌]\n[00:00.62]第一句\n[00:02.00]第二句\n[00:03.50]\n"
+        );
+        assert_eq!(
+            lrc.to_srt(),
+            "1\n00:00:00,623 --> 00:00:02,000\n第一句\n\n2\n00:00:02,000 --> 00:00:03,500\n第二句\n\n"
+        );
+        assert_eq!(lrc.to_plain(), "第一句\n第二句\n");
+        assert!(Lrc::parse("[ar:歌手]", CreditLines::Keep).is_empty());
+    }
+
+    #[test]
+    fn test_credit_lines() {
+        let content = "[00:00.00]Composed by : Someone\n[00:00.50]\n[00:01.00]Open up: let me in\n\
+            [00:02.00]词: 只在开头\n[00:03.00]Mix: after the first line";
+        let lrc = Lrc::parse(content, CreditLines::Strip);
+        assert_eq!(
+            lrc.to_plain(),
+            "Open up: let me in\n词: 只在开头\nMix: after the first line\n"
+        );
+        assert!(!is_credit("Open up: let me in"));
+        assert!(!is_credit("Opportunity: knocks"));
+        assert!(is_credit("作 词 ： 某人"));
+    }
+
+    #[test]
+    fn test_compose() {
+        let lyrics = SongLyrics {
+            lyric: "[ti:歌]\n[00:01.00]こんにちは\n[00:05.00]さようなら\n[00:09.00]♪".to_string(),
+            translation: "[00:01.000]你好\n[00:05.05]再见\n[00:09.00]//".to_string(),
+            romaji: "[00:01.00]konnichiwa".to_string(),
+            ..Default::default()
+        };
+        let variants = [LyricVariant::Translation, LyricVariant::Romaji];
+        let (lrc, extras) = compose(&lyrics, &variants, LyricsStyle::Separate);
+        assert_eq!(lrc, lyrics.lyric);
+        assert_eq!(extras.len(), 2);
+        assert_eq!(extras[0].0, LyricVariant::Translation);
+
+        let (lrc, extras) = compose(&lyrics, &variants, LyricsStyle::Inline);
+        assert!(extras.is_empty());
+        assert_eq!(
+            lrc,
+            "[ti:歌]\n[00:01.00]こんにちは / 你好 / konnichiwa\n[00:05.00]さようなら / 再见\n[00:09.00]♪"
+        );
+
+        let (lrc, _) = compose(&lyrics, &variants[..1], LyricsStyle::Interleave);
+        assert_eq!(
+            lrc,
+            "[ti:歌]\n[00:01.00]こんにちは\n[00:01.00]你好\n[00:05.00]さようなら\n[00:05.00]再见\n[00:09.00]♪"
+        );
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(plain_text(LRC), "第一句\n第二句");
+        assert_eq!(parse_timestamp("ab:01"), None);
+        assert_eq!(parse_timestamp("01:02.5"), Some(62_500));
+        assert_eq!(format_timestamp(62_504), "01:02.50");
+        assert_eq!(format_timestamp(3_599_996), "60:00.00");
+    }
+}
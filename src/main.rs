@@ -1,29 +1,57 @@
+mod api;
 mod cli;
+mod collision;
 mod config;
+mod cover;
 mod download;
+mod export;
+mod karaoke;
+mod library;
+mod lyrics;
+mod manifest;
 mod metadata;
+mod paths;
+mod playlist;
+mod quality;
+mod report;
+mod retag;
+mod session;
+mod template;
+mod track;
 mod util;
 use std::{
+    collections::HashMap,
     fs::File,
     io::{BufReader, ErrorKind},
-    path::{Path, PathBuf},
+    path::Path,
     sync::Arc,
 };
 
 use anyhow::bail;
+use clap::Parser;
 use indicatif::{ProgressBar, ProgressStyle};
 use ncm_api::MusicApi;
 use tokio::{
     self,
     fs::{self},
     io::AsyncWriteExt,
-    sync::{Mutex, Semaphore},
+    sync::{Mutex, OnceCell, Semaphore},
 };
 
 use crate::{
-    config::{Config, ConfigError},
+    api::{ExtraApi, SongDetail},
+    cli::{Args, Command, ConfigAction},
+    collision::PathRegistry,
+    config::{Config, ConfigLayers, JobMode, MinBitratePolicy, PreviewPolicy},
+    cover::{CoverCache, FolderImages},
     download::{DownloadOptions, download_file},
-    metadata::{TrackInfo, write_metadata},
+    library::Library,
+    lyrics::Lrc,
+    metadata::{TrackInfo, probe_format, write_metadata},
+    paths::Paths,
+    report::Report,
+    template::{PathTemplate, RenderedPath, TemplateValues},
+    track::{AlbumCache, ArtistCache, Track},
 };
 
 const MAX_CONS: usize = 0;
@@ -31,34 +59,69 @@ const MAX_NAME_LENGTH: usize = 200;
 const CTCODE: &str = "86";
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let config_path: &Path = Path::new("config.yml");
-    let cookie_path: &Path = Path::new("cookie.json");
-    let content = match fs::read_to_string(config_path).await {
-        Ok(v) => v,
-        Err(e) => match e.kind() {
-            ErrorKind::NotFound => {
-                let _ = fs::write(config_path, Config::generate_default()).await;
-                bail!("未读取到配置文件！已生成默认配置文件，请进行配置后重新运行本程序");
-            }
-            _ => {
+    let args = Args::parse();
+    let paths = Paths::resolve(&args);
+    let config_path: &Path = &paths.config;
+    let cookie_path: &Path = &paths.cookie;
+    match &args.command {
+        Some(Command::ImportCookie { source }) => return import_cookie(source, cookie_path).await,
+        Some(Command::Whoami) => return whoami(cookie_path).await,
+        Some(Command::Logout) => return logout(cookie_path).await,
+        Some(Command::Download)
+        | Some(Command::Retag { .. })
+        | Some(Command::Export { .. })
+        | Some(Command::Config { .. })
+        | None => {}
+    }
+    let mut layers = ConfigLayers::default();
+    let mut config_found = false;
+    for (source, path) in paths.config_files() {
+        let content = match fs::read_to_string(&path).await {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(_) => {
                 bail!(
-                    "读取配置文件错误！可以尝试删除现有的配置文件并重新运行程序以自动生成默认配置文件",
+                    "读取配置文件 {} 错误！可以尝试删除现有的配置文件并重新运行程序以自动生成默认配置文件",
+                    path.display()
                 );
             }
-        },
-    };
-    let config = Arc::new(match Config::load(content.as_str()) {
+        };
+        config_found = true;
+        if let Err(e) = layers.merge_yaml(&content, source) {
+            bail!("{}", e);
+        }
+    }
+    if let Err(e) = layers.merge_env(std::env::vars()) {
+        bail!("{}", e);
+    }
+    if let Err(e) = layers.merge_overrides(&args.overrides) {
+        bail!("{}", e);
+    }
+    if let Some(Command::Config {
+        action: ConfigAction::Show,
+    }) = &args.command
+    {
+        let _ = cli::print(layers.show().trim_end()).await;
+        return Ok(());
+    }
+    if !config_found {
+        let _ = paths::ensure_parent(config_path);
+        let _ = fs::write(config_path, Config::generate_default()).await;
+        bail!(
+            "未读取到配置文件！已生成默认配置文件 {}，请进行配置后重新运行本程序",
+            config_path.display()
+        );
+    }
+    let config = Arc::new(match layers.build() {
         Ok(v) => v,
-        Err(e) => match e {
-            ConfigError::Parse(_) => {
-                bail!("max_bitrate_level设置有误");
-            }
-            _ => {
-                bail!("配置文件解析错误");
-            }
-        },
+        Err(e) => {
+            bail!("{}", e);
+        }
     });
-    let api = Arc::new(Mutex::new(
+    let api = Arc::new(Mutex::new(if config.guest {
+        let _ = cli::print("当前为游客模式，仅能下载无需登录即可获取的歌曲").await;
+        MusicApi::new(MAX_CONS)
+    } else {
         match File::open(cookie_path).map(BufReader::new) {
             Ok(reader) => {
                 let Ok(cookie_jar) = cookie_store::serde::json::load(reader) else {
@@ -98,43 +161,150 @@ async fn main() -> anyhow::Result<()> {
                     bail!("cookie文件读取错误，可以删除cookie.json重试");
                 }
             },
-        },
-    ));
-    {
-        let Ok(mut writer) = std::fs::File::create(cookie_path).map(std::io::BufWriter::new) else {
-            bail!("cookie文件写入失败，可以删除cookie.json重试");
-        };
-        let cookie_jar = api.lock().await.cookie_jar();
-        let store = cookie_jar.lock().unwrap();
-        let Ok(_) = cookie_store::serde::json::save(&store, &mut writer) else {
+        }
+    }));
+    if !config.guest {
+        let Ok(_) = session::save_cookie_jar(&*api.lock().await, cookie_path) else {
             bail!("cookie文件写入失败，可以删除cookie.json重试");
         };
-    }
-    match api.lock().await.login_status().await {
-        Ok(info) => {
-            let _ = cli::print(&format!("已以 {} 身份成功登录！", info.nickname)).await;
+        match api.lock().await.login_status().await {
+            Ok(info) => {
+                let _ = cli::print(&format!("已以 {} 身份成功登录！", info.nickname)).await;
+            }
+            Err(e) => {
+                bail!("登录错误：{}", e);
+            }
         }
+    }
+    let extra_api = match ExtraApi::new(api.clone(), config.timeout) {
+        Ok(v) => Arc::new(v),
         Err(e) => {
-            bail!("登录错误：{}", e);
+            bail!("初始化网络请求失败：{}", e);
         }
+    };
+    if let Some(Command::Retag { dir, dry_run }) = &args.command {
+        let dir = dir.clone().unwrap_or_else(|| paths.output_dir.clone());
+        return retag::run(&dir, *dry_run, &config, extra_api).await;
     }
-    let _ = cli::print("请输入要下载的歌单Id：").await;
-    let Ok(playlist_id) = cli::input().await.unwrap().parse::<u64>() else {
-        bail!("歌单Id格式错误！");
+    // export命令与metadata模式相同，只是格式和歌单Id可以由参数指定
+    let (export_formats, export_id) = match &args.command {
+        Some(Command::Export { id, formats }) if formats.is_empty() => {
+            (Some(config.metadata_formats.clone()), *id)
+        }
+        Some(Command::Export { id, formats }) => (Some(formats.clone()), *id),
+        _ if config.mode == JobMode::Metadata => (Some(config.metadata_formats.clone()), None),
+        _ => (None, None),
+    };
+    let playlist_id = match export_id {
+        Some(v) => v,
+        None => {
+            let _ = cli::print(if export_formats.is_some() {
+                "请输入要导出的歌单Id："
+            } else {
+                "请输入要下载的歌单Id："
+            })
+            .await;
+            let Ok(playlist_id) = cli::input().await.unwrap().parse::<u64>() else {
+                bail!("歌单Id格式错误！");
+            };
+            playlist_id
+        }
     };
     let Ok(playlist_detail) = api.lock().await.song_list_detail(playlist_id).await else {
         bail!("歌单Id错误！");
     };
-    let folder_name = util::truncate_filename(&playlist_detail.name, MAX_NAME_LENGTH);
-    let folder_path = Arc::new(PathBuf::from(folder_name));
+    let output_dir = Arc::new(paths.output_dir.clone());
+    let _ = fs::create_dir_all(output_dir.as_ref()).await;
+    let _ = cli::print(&if export_formats.is_some() {
+        format!("正在导出 {} 歌单", playlist_detail.name)
+    } else {
+        format!("正在下载 {} 歌单", playlist_detail.name)
+    })
+    .await;
 
-    if Path::new(folder_path.as_ref()).exists() {
-        let _ = fs::remove_dir_all(folder_path.as_ref()).await;
+    let template =
+        Arc::new(PathTemplate::new(&config.dir_template, &config.file_template).unwrap());
+    // 写入标签和导出歌曲信息时也需要歌曲详情和专辑详情
+    let full_metadata = config.downloads_songs() || export_formats.is_some();
+    let needs_album = template.uses("album_artist") || full_metadata;
+    let needs_detail = template.needs_detail() || full_metadata;
+    let song_details: Arc<HashMap<u64, SongDetail>> = Arc::new(if needs_detail {
+        let ids: Vec<u64> = playlist_detail.songs.iter().map(|v| v.id).collect();
+        match extra_api.songs_detail(&ids).await {
+            Ok(details) => details.into_iter().map(|v| (v.id, v)).collect(),
+            Err(e) => {
+                log::warn!("Failed to get song details: {}", e);
+                HashMap::new()
+            }
+        }
+    } else {
+        HashMap::new()
+    });
+    let album_cache = Arc::new(AlbumCache::new(extra_api.clone()));
+    if let Some(export_formats) = &export_formats {
+        let added_times = match extra_api.playlist_added_times(playlist_id).await {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!(
+                    "Failed to get added times of playlist {}: {}",
+                    playlist_id,
+                    e
+                );
+                HashMap::new()
+            }
+        };
+        let mut records = Vec::new();
+        for (index, song_info) in playlist_detail.songs.iter().enumerate() {
+            let mut track = Track::from_song_info(song_info, index + 1);
+            if let Some(detail) = song_details.get(&track.id) {
+                track.apply_detail(detail);
+            }
+            if let Some(album) = album_cache.get(track.album_id).await {
+                track.apply_album(&album);
+            }
+            let mut record = export::Record::new(&track);
+            record.added_at = added_times
+                .get(&track.id)
+                .and_then(|&v| util::datetime_from_timestamp(v));
+            records.push(record);
+        }
+        let file_name = playlist_file_name(&playlist_detail.name, playlist_id, &config);
+        for format in export_formats.iter() {
+            let path = output_dir.join(format!("{}.{}", file_name, format.extension()));
+            match export::write(&path, *format, &records) {
+                Ok(_) => {
+                    let _ = cli::print(&format!("已导出歌曲信息到 {}", path.display())).await;
+                }
+                Err(e) => {
+                    let _ = cli::print(&format!("歌曲信息导出失败：{:#}", e)).await;
+                }
+            }
+        }
+        return Ok(());
     }
-    let _ = fs::create_dir(folder_path.as_ref()).await;
-    let _ = cli::print(&format!("正在下载 {} 歌单", playlist_detail.name)).await;
+    let cover_cache = Arc::new(CoverCache::new(
+        config.cover_size,
+        DownloadOptions::new(config.retry, config.retry_delay, config.timeout),
+    ));
+    // 文件夹中的封面单独缓存，尺寸可能与写入标签的不同
+    let folder_cover_cache = Arc::new(CoverCache::new(
+        config.folder_cover_size,
+        DownloadOptions::new(config.retry, config.retry_delay, config.timeout),
+    ));
+    let artist_cache = Arc::new(ArtistCache::new(extra_api.clone()));
+    let folder_images = Arc::new(FolderImages::new(config.overwrite_images));
+    // 补全歌词时按需扫描输出目录中歌曲文件的标签
+    let library: Arc<OnceCell<Library>> = Arc::new(OnceCell::new());
+    let registry = Arc::new(Mutex::new(PathRegistry::new(
+        &output_dir,
+        config.collision_strategy,
+        config.filename_rules(),
+        MAX_NAME_LENGTH,
+    )));
+    let playlist_name = Arc::new(playlist_detail.name.clone());
+    let playlist_len = playlist_detail.songs.len();
 
-    let progress_bar = ProgressBar::new(playlist_detail.songs.len() as u64);
+    let progress_bar = ProgressBar::new(playlist_len as u64);
     progress_bar.set_style(
         ProgressStyle::default_bar()
             .template("正在下载 [{bar}] {pos}/{len}")
@@ -142,113 +312,374 @@ async fn main() -> anyhow::Result<()> {
             .progress_chars("=> "),
     );
     progress_bar.inc(0);
-    let failed_songs: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
-    let failed_lyrics: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let report = Arc::new(Mutex::new(Report::default()));
+    let playlist_entries = Arc::new(Mutex::new(
+        playlist_detail
+            .songs
+            .iter()
+            .enumerate()
+            .map(|(index, v)| playlist::Entry::new(&Track::from_song_info(v, index + 1)))
+            .collect::<Vec<_>>(),
+    ));
+    let levels = Arc::new(config.max_bitrate_level.levels());
     let semaphore = Arc::new(Semaphore::new(config.concurrency));
     let mut join_handles = Vec::new();
-    for song_info in playlist_detail.songs {
+    for (index, song_info) in playlist_detail.songs.into_iter().enumerate() {
         let permit = semaphore.clone().acquire_owned().await.unwrap();
-        let failed_songs = failed_songs.clone();
-        let failed_lyrics = failed_lyrics.clone();
-        let api = api.clone();
+        let report = report.clone();
+        let levels = levels.clone();
+        let extra_api = extra_api.clone();
         let config = config.clone();
-        let folder_path = folder_path.clone();
+        let output_dir = output_dir.clone();
+        let template = template.clone();
+        let song_details = song_details.clone();
+        let album_cache = album_cache.clone();
+        let cover_cache = cover_cache.clone();
+        let folder_cover_cache = folder_cover_cache.clone();
+        let artist_cache = artist_cache.clone();
+        let folder_images = folder_images.clone();
+        let registry = registry.clone();
+        let library = library.clone();
+        let playlist_entries = playlist_entries.clone();
+        let playlist_name = playlist_name.clone();
         let progress_bar = progress_bar.clone();
         join_handles.push(tokio::spawn(async move {
-            let song_file_base_name = format!(
-                "{}{} - {}",
-                song_info.name,
-                match song_info.translated_name {
-                    Some(v) => format!("({})", v),
-                    None => String::new(),
-                },
-                song_info.singer.join(", ")
-            );
-            if config.download_songs {
-                let Ok(song_url) = api
-                    .lock()
-                    .await
-                    .songs_url(&[song_info.id], &config.max_bitrate_level)
-                    .await
+            let mut track = Track::from_song_info(&song_info, index + 1);
+            if let Some(detail) = song_details.get(&track.id) {
+                track.apply_detail(detail);
+            }
+            if needs_album && let Some(album) = album_cache.get(track.album_id).await {
+                track.apply_album(&album);
+            }
+            let display_name = track.display_name();
+            let mut song_url = None;
+            let mut preview = false;
+            if config.downloads_songs() {
+                let Ok(selected) =
+                    quality::select_song_url(&extra_api, track.id, track.duration, &levels).await
                 else {
-                    failed_songs.lock().await.push(song_file_base_name.clone());
+                    report.lock().await.failed_songs.push(display_name.clone());
+                    return;
+                };
+                let Some(selected) = selected else {
+                    if config.guest {
+                        report
+                            .lock()
+                            .await
+                            .unavailable_songs
+                            .push(display_name.clone());
+                    } else {
+                        report.lock().await.failed_songs.push(display_name.clone());
+                    }
+                    return;
+                };
+                if let Some(min_level) = config.min_bitrate_level()
+                    && !quality::meets_min_level(&selected, min_level)
+                {
+                    let description = format!(
+                        "{}({} {}kbps)",
+                        display_name,
+                        selected.level.as_deref().unwrap_or("unknown"),
+                        selected.br / 1000
+                    );
+                    match config.min_bitrate_policy {
+                        MinBitratePolicy::Skip => {
+                            report.lock().await.below_min_skipped.push(description);
+                            return;
+                        }
+                        MinBitratePolicy::Fail => {
+                            report.lock().await.failed_songs.push(description);
+                            return;
+                        }
+                        MinBitratePolicy::Flag => {
+                            report.lock().await.below_min_flagged.push(description);
+                        }
+                    }
+                }
+                if quality::is_preview(&selected, track.duration) {
+                    let mut report = report.lock().await;
+                    report.preview_songs.push(display_name.clone());
+                    // 游客模式下会员歌曲只返回试听片段，登录后才能获取完整歌曲
+                    if config.guest {
+                        report.unavailable_songs.push(display_name.clone());
+                    }
+                    drop(report);
+                    match config.preview_policy {
+                        PreviewPolicy::Skip => return,
+                        PreviewPolicy::Save => preview = true,
+                    }
+                }
+                song_url = Some(selected);
+            }
+            let obtained_level = song_url
+                .as_ref()
+                .map(|v| v.level.clone().unwrap_or_else(|| levels[0].to_string()));
+            let values = TemplateValues::new(
+                &track,
+                &playlist_name,
+                playlist_len,
+                obtained_level.as_deref(),
+            );
+            let rendered = template.render(&values, MAX_NAME_LENGTH, &config.filename_rules());
+            let rendered = if config.mode == JobMode::Lyrics {
+                // 只为已有的歌曲文件补全歌词，歌词文件与歌曲文件同名
+                let previous = registry.lock().await.previous_files(track.id);
+                let audio = match previous
+                    .iter()
+                    .find_map(|stem| playlist::find_audio(&output_dir, stem))
+                    .or_else(|| {
+                        playlist::find_audio(&output_dir, &rendered.dir.join(&rendered.stem))
+                    }) {
+                    Some(v) => Some(v),
+                    None => library
+                        .get_or_init(|| async {
+                            let dir = output_dir.to_path_buf();
+                            tokio::task::spawn_blocking(move || Library::scan(&dir))
+                                .await
+                                .unwrap_or_default()
+                        })
+                        .await
+                        .find(track.id)
+                        .map(Path::to_path_buf),
+                };
+                let Some(audio) = audio else {
+                    report.lock().await.missing_audio_songs.push(display_name);
+                    progress_bar.inc(1);
+                    return;
+                };
+                playlist_entries.lock().await[index].path = Some(audio.clone());
+                let rendered = RenderedPath {
+                    dir: audio.parent().map(Path::to_path_buf).unwrap_or_default(),
+                    stem: audio
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string(),
+                };
+                if output_dir
+                    .join(rendered.file(config.lyrics_format.extension()))
+                    .exists()
+                {
+                    progress_bar.inc(1);
                     return;
+                }
+                rendered
+            } else {
+                registry.lock().await.claim(rendered, &track).await
+            };
+            let folder_path = output_dir.join(&rendered.dir);
+            if fs::create_dir_all(&folder_path).await.is_err() {
+                report.lock().await.failed_songs.push(display_name.clone());
+                return;
+            }
+            let song_lyrics = if config.downloads_songs() || config.downloads_lyrics() {
+                match extra_api.lyrics(track.id).await {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        log::warn!("Failed to get lyric for {}: {}", display_name, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            if let Some(song_lyrics) = &song_lyrics {
+                track.apply_lyric_credits(&song_lyrics.lyric);
+            }
+            // 合并翻译后再规范化，署名行按配置保留或删除
+            let composed = song_lyrics.as_ref().map(|v| {
+                let (lyric, extras) =
+                    lyrics::compose(v, &config.lyrics_variants, config.lyrics_style);
+                let parse = |content: &str| Lrc::parse(content, config.lyrics_credits);
+                let extras: Vec<_> = extras
+                    .into_iter()
+                    .map(|(variant, content)| (variant, parse(&content)))
+                    .collect();
+                (parse(&lyric), extras)
+            });
+            let lyric = composed.as_ref().map(|(v, _)| v.to_lrc());
+            if let Some(song_url) = song_url {
+                let audio_base_name = if preview {
+                    format!("{} [preview]", rendered.stem)
+                } else {
+                    rendered.stem.clone()
                 };
-                let song_url = song_url.first().unwrap();
-                let song_file_name = format!("{}.{}", song_file_base_name, song_url.extension);
-                let cover_file_name = format!("{}.jpg", song_file_base_name);
-                let song_path = folder_path.join(song_file_name);
-                let cover_path = folder_path.join(cover_file_name);
+                let ext = song_url
+                    .extension
+                    .as_deref()
+                    .unwrap_or("mp3")
+                    .to_lowercase();
+                let song_file_name = format!("{}.{}", audio_base_name, ext);
+                let mut song_file = rendered.dir.join(song_file_name);
+                let mut song_path = output_dir.join(&song_file);
                 let Ok(_) = download_file(
-                    &url::Url::parse(&song_url.url).unwrap(),
+                    &url::Url::parse(song_url.url.as_deref().unwrap_or_default()).unwrap(),
                     &song_path,
                     DownloadOptions::new(config.retry, config.retry_delay, config.timeout),
                 )
                 .await
                 else {
-                    failed_songs.lock().await.push(song_file_base_name.clone());
+                    report.lock().await.failed_songs.push(display_name.clone());
                     return;
                 };
-                let ext = &song_url.extension;
-                if ext == "mp3" || ext == "flac" {
-                    let Ok(_) = download_file(
-                        &url::Url::parse(&song_info.pic_url).unwrap(),
-                        &cover_path,
-                        DownloadOptions::new(config.retry, config.retry_delay, config.timeout),
-                    )
+                registry.lock().await.record(&rendered, track.id);
+                report
+                    .lock()
                     .await
-                    else {
-                        failed_songs.lock().await.push(song_file_base_name.clone());
-                        return;
-                    };
-                    let cover_data = match fs::read(&cover_path).await {
-                        Ok(data) => data,
-                        Err(_) => {
-                            failed_songs.lock().await.push(song_file_base_name.clone());
-                            return;
+                    .obtained_levels
+                    .push((display_name.clone(), obtained_level.unwrap_or_default()));
+                let format = match probe_format(&song_path) {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        log::warn!("Failed to probe format of {}: {}", display_name, e);
+                        None
+                    }
+                };
+                // 服务器返回的格式与实际不符时按实际格式修改扩展名
+                if let Some(format) = format
+                    && format.extension() != ext
+                {
+                    let real_file =
+                        rendered
+                            .dir
+                            .join(format!("{}.{}", audio_base_name, format.extension()));
+                    let real_path = output_dir.join(&real_file);
+                    match fs::rename(&song_path, &real_path).await {
+                        Ok(_) => {
+                            song_file = real_file;
+                            song_path = real_path;
                         }
-                    };
+                        Err(e) => log::warn!("Failed to rename {}: {}", song_path.display(), e),
+                    }
+                }
+                playlist_entries.lock().await[index].path = Some(song_file);
+                if !config.folder_cover.is_empty()
+                    && template.is_album_dir()
+                    && !track.cover_url.is_empty()
+                    && folder_images
+                        .claim(&folder_path, &config.folder_cover)
+                        .await
+                    && let Some(cover) = folder_cover_cache.get(&track.cover_url).await
+                    && let Err(e) = folder_images
+                        .save(&folder_path, &config.folder_cover, &cover)
+                        .await
+                {
+                    log::warn!("Failed to save folder cover: {:#}", e);
+                }
+                if !config.artist_image.is_empty()
+                    && let Some((dir, placeholder)) =
+                        template.artist_dir(&values, MAX_NAME_LENGTH, &config.filename_rules())
+                    && !dir.as_os_str().is_empty()
+                    && let Some(artist_id) = match placeholder {
+                        "album_artist" => track.album_artist_id,
+                        _ => track.artist_id,
+                    }
+                {
+                    let artist_path = output_dir.join(dir);
+                    if folder_images
+                        .claim(&artist_path, &config.artist_image)
+                        .await
+                        && let Some(url) = artist_cache.picture_url(artist_id).await
+                        && let Some(image) = folder_cover_cache.get(&url).await
+                        && let Err(e) = folder_images
+                            .save(&artist_path, &config.artist_image, &image)
+                            .await
+                    {
+                        log::warn!("Failed to save artist image: {:#}", e);
+                    }
+                }
+                if format.is_some_and(|v| v.taggable()) {
+                    let cover = cover_cache.get(&track.cover_url).await;
+                    let release_date = track.release_date_string();
                     let track_info = TrackInfo {
-                        title: &song_info.name,
-                        artists: &song_info.singer.iter().map(|v| v.as_str()).collect(),
-                        album: &song_info.album,
-                        cover_data: &cover_data,
-                        cover_mime_type: lofty::picture::MimeType::Jpeg,
+                        title: &track.title,
+                        artists: &track.artists.iter().map(|v| v.as_str()).collect(),
+                        album: &track.album,
+                        album_artist: track.album_artist.as_deref(),
+                        track: (track.track_number, track.track_total),
+                        disc: (track.disc_number, track.disc_total),
+                        release_date: release_date.as_deref(),
+                        genre: track.genre.as_deref(),
+                        composer: track.composer.as_deref(),
+                        lyricist: track.lyricist.as_deref(),
+                        song_id: track.id,
+                        album_id: Some(track.album_id).filter(|&v| v > 0),
+                        lyrics: lyric.as_deref().filter(|_| config.embed_lyrics),
+                        cover: cover.as_deref(),
                     };
 
-                    if let Err(e) = write_metadata(ext, &song_path, &track_info) {
-                        log::warn!(
-                            "Failed to write metadata for {}: {}",
-                            song_file_base_name,
-                            e
-                        );
-                    }
-                    if let Err(e) = fs::remove_file(&cover_path).await {
-                        log::warn!("Failed to delete cover file: {}", e);
+                    if let Err(e) = write_metadata(&song_path, &track_info) {
+                        log::warn!("Failed to write metadata for {}: {}", display_name, e);
+                        report
+                            .lock()
+                            .await
+                            .untagged_songs
+                            .push(display_name.clone());
                     }
+                } else {
+                    report.lock().await.untagged_songs.push(format!(
+                        "{}({})",
+                        display_name,
+                        format.map(|v| v.extension()).unwrap_or("未知格式")
+                    ));
                 }
             }
-            if config.download_lyrics {
-                let lyric_file_name = format!("{}.lrc", song_file_base_name);
-                let lyric_path = folder_path.join(lyric_file_name);
-                if let Ok(v) = api.lock().await.song_lyric(song_info.id).await {
-                    let lyric_content = v.lyric.join("\n");
+            if config.downloads_lyrics()
+                && let Some((lyric_content, extra_lyrics)) = composed
+            {
+                let karaoke_lines = song_lyrics
+                    .as_ref()
+                    .map(|v| karaoke::parse_yrc(&v.karaoke))
+                    .unwrap_or_default();
+                let karaoke_files = config
+                    .karaoke_lyrics
+                    .iter()
+                    .filter(|_| !karaoke_lines.is_empty())
+                    .map(|format| {
+                        (
+                            rendered.file(format.extension()),
+                            format.export(&karaoke_lines, &track.title),
+                        )
+                    });
+                let extension = config.lyrics_format.extension();
+                let files = std::iter::once((rendered.file(extension), lyric_content))
+                    .chain(extra_lyrics.into_iter().map(|(variant, content)| {
+                        let extension = format!("{}.{}", variant.suffix(), extension);
+                        (rendered.file(&extension), content)
+                    }))
+                    .filter(|(_, lrc)| !lrc.is_empty())
+                    .map(|(file, lrc)| (file, config.lyrics_format.serialize(&lrc)))
+                    .chain(karaoke_files);
+                let mut written = false;
+                for (lyric_file, lyric_content) in files {
+                    let lyric_path = output_dir.join(lyric_file);
+                    // 补全歌词时不覆盖已有的文件
+                    if config.mode == JobMode::Lyrics && lyric_path.exists() {
+                        continue;
+                    }
                     let Ok(mut writer) = tokio::fs::File::create(lyric_path)
                         .await
                         .map(tokio::io::BufWriter::new)
                     else {
-                        failed_lyrics.lock().await.push(song_file_base_name);
+                        report.lock().await.failed_lyrics.push(display_name);
                         return;
                     };
                     let Ok(_) = writer.write_all(lyric_content.as_bytes()).await else {
-                        failed_lyrics.lock().await.push(song_file_base_name);
+                        report.lock().await.failed_lyrics.push(display_name);
                         return;
                     };
                     let Ok(_) = writer.flush().await else {
-                        failed_lyrics.lock().await.push(song_file_base_name);
+                        report.lock().await.failed_lyrics.push(display_name);
                         return;
                     };
-                };
+                    written = true;
+                }
+                if written {
+                    match config.mode {
+                        JobMode::Lyrics => report.lock().await.completed_lyrics += 1,
+                        _ => registry.lock().await.record(&rendered, track.id),
+                    }
+                }
             }
             progress_bar.inc(1);
             drop(permit);
@@ -257,19 +688,132 @@ async fn main() -> anyhow::Result<()> {
     for handle in join_handles {
         handle.await.unwrap();
     }
+    if let Err(e) = registry.lock().await.save() {
+        log::warn!("Failed to save manifest: {}", e);
+    }
+    if !config.playlist_formats.is_empty() {
+        let mut entries = playlist_entries.lock().await;
+        // 本次没有下载成功的歌曲使用之前下载的文件
+        let registry = registry.lock().await;
+        for entry in entries.iter_mut().filter(|v| v.path.is_none()) {
+            entry.path = registry
+                .previous_files(entry.id)
+                .iter()
+                .find_map(|stem| playlist::find_audio(&output_dir, stem));
+        }
+        if entries.iter().any(|v| v.path.is_some()) {
+            let file_name = playlist_file_name(&playlist_name, playlist_id, &config);
+            for format in config.playlist_formats.iter() {
+                let path = output_dir.join(format!("{}.{}", file_name, format.extension()));
+                if let Err(e) = playlist::write(
+                    &path,
+                    *format,
+                    &playlist_name,
+                    &entries,
+                    config.playlist_failed_tracks,
+                ) {
+                    let _ = cli::print(&format!("播放列表生成失败：{:#}", e)).await;
+                }
+            }
+        }
+    }
     let _ = cli::print("下载完成！").await;
-    {
-        let failed = failed_songs.lock().await;
-        if !failed.is_empty() {
-            let _ = cli::print(&format!("歌曲下载失败：{}", failed.join(", "))).await;
+    report
+        .lock()
+        .await
+        .print(&config, config.max_bitrate_level.preferred())
+        .await;
+    Ok(())
+}
+
+/// 播放列表等文件使用的文件名(不含扩展名)，歌单名清理后为空时使用歌单Id
+fn playlist_file_name(name: &str, id: u64, config: &Config) -> String {
+    let file_name = util::truncate_filename(name, MAX_NAME_LENGTH, &config.filename_rules());
+    if file_name.is_empty() {
+        id.to_string()
+    } else {
+        file_name
+    }
+}
+
+/// 导入浏览器导出的cookie，验证登录状态后保存
+async fn import_cookie(source: &str, cookie_path: &Path) -> anyhow::Result<()> {
+    let content = if Path::new(source).is_file() {
+        let Ok(content) = fs::read_to_string(source).await else {
+            bail!("cookie文件读取错误！");
+        };
+        content
+    } else {
+        source.to_string()
+    };
+    let cookie_jar = match session::parse_cookies(&content) {
+        Ok(v) => v,
+        Err(e) => {
+            bail!("cookie解析错误：{}", e);
+        }
+    };
+    let api = MusicApi::from_cookie_jar(cookie_jar, MAX_CONS);
+    match api.login_status().await {
+        Ok(info) => {
+            let _ = cli::print(&format!("已以 {} 身份成功登录！", info.nickname)).await;
+        }
+        Err(e) => {
+            bail!("导入的cookie无效：{}", e);
         }
     }
-    {
-        let failed = failed_lyrics.lock().await;
-        if !failed.is_empty() {
-            let _ = cli::print(&format!("歌词下载失败：{}", failed.join(", "))).await;
+    let Ok(_) = session::save_cookie_jar(&api, cookie_path) else {
+        bail!("cookie文件写入失败！");
+    };
+    let _ = cli::print("cookie导入成功！").await;
+    Ok(())
+}
+
+/// 显示已保存的登录状态
+async fn whoami(cookie_path: &Path) -> anyhow::Result<()> {
+    if !cookie_path.exists() {
+        bail!("尚未登录！");
+    }
+    let Ok(cookie_jar) = session::load_cookie_jar(cookie_path) else {
+        bail!("cookie文件解析错误，可以删除cookie.json重试");
+    };
+    let api = MusicApi::from_cookie_jar(cookie_jar, MAX_CONS);
+    let info = match api.login_status().await {
+        Ok(v) => v,
+        Err(e) => {
+            bail!("登录状态已失效：{}", e);
         }
+    };
+    let vip = match info.vip_type {
+        0 => "无".to_string(),
+        10 => "音乐包".to_string(),
+        11 => "黑胶VIP".to_string(),
+        v => format!("未知({})", v),
+    };
+    let expiry = session::login_cookie_expiry(&api).unwrap_or_else(|| "会话结束时".to_string());
+    let _ = cli::print(&format!("昵称：{}", info.nickname)).await;
+    let _ = cli::print(&format!("用户Id：{}", info.uid)).await;
+    let _ = cli::print(&format!("VIP类型：{}", vip)).await;
+    let _ = cli::print(&format!("登录过期时间：{}", expiry)).await;
+    Ok(())
+}
+
+/// 退出登录并删除cookie文件
+async fn logout(cookie_path: &Path) -> anyhow::Result<()> {
+    if !cookie_path.exists() {
+        bail!("尚未登录！");
     }
+    match session::load_cookie_jar(cookie_path) {
+        Ok(cookie_jar) => {
+            MusicApi::from_cookie_jar(cookie_jar, MAX_CONS)
+                .logout()
+                .await
+        }
+        Err(e) => log::warn!("Failed to load cookie file before logout: {}", e),
+    }
+    let Ok(_) = session::remove_cookie_file(cookie_path) else {
+        bail!("cookie文件删除失败，请手动删除cookie.json");
+    };
+    let _ = cli::print("已退出登录！").await;
     Ok(())
 }
 
@@ -279,6 +823,9 @@ mod tests {
 
     #[test]
     fn test() {
-        println!("{}", util::truncate_filename("", MAX_NAME_LENGTH));
+        println!(
+            "{}",
+            util::truncate_filename("", MAX_NAME_LENGTH, &Default::default())
+        );
     }
 }
@@ -0,0 +1,122 @@
+//! 输出目录中记录文件与歌曲对应关系的清单
+//!
+//! 用于区分已有的文件是同一首歌曲之前下载的，还是其它歌曲或用户自己的文件
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+const MANIFEST_FILE_NAME: &str = ".ncmdownloader.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// 相对于输出目录、不含扩展名的文件路径 => 歌曲Id
+    #[serde(default)]
+    files: BTreeMap<String, u64>,
+}
+
+impl Manifest {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(MANIFEST_FILE_NAME)
+    }
+
+    /// 读取输出目录中的清单，不存在或无法解析时返回空清单
+    pub fn load(output_dir: &Path) -> Self {
+        let path = Self::path(output_dir);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match serde_json::from_str(&content) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Failed to parse manifest {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    /// 在文件夹及其上级文件夹中查找清单，返回清单和该文件夹相对于清单所在文件夹的路径
+    pub fn find(dir: &Path) -> Option<(Self, PathBuf)> {
+        let dir = dir.canonicalize().ok()?;
+        let root = dir.ancestors().find(|v| Self::path(v).is_file())?;
+        let prefix = dir.strip_prefix(root).ok()?.to_path_buf();
+        Some((Self::load(root), prefix))
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        let path = Self::path(output_dir);
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write manifest {}", path.display()))
+    }
+
+    /// 文件对应的歌曲Id
+    pub fn get(&self, key: &Path) -> Option<u64> {
+        self.files.get(&Self::key(key)).copied()
+    }
+
+    /// 不区分大小写时与该文件相同的所有记录对应的歌曲Id
+    pub fn get_ignore_case(&self, key: &Path) -> Vec<u64> {
+        let key = Self::key(key).to_lowercase();
+        self.files
+            .iter()
+            .filter(|(k, _)| k.to_lowercase() == key)
+            .map(|(_, v)| *v)
+            .collect()
+    }
+
+    /// 记录为该歌曲的所有文件
+    pub fn files_of(&self, id: u64) -> impl Iterator<Item = PathBuf> + '_ {
+        self.files
+            .iter()
+            .filter(move |(_, v)| **v == id)
+            .map(|(k, _)| PathBuf::from(k))
+    }
+
+    pub fn insert(&mut self, key: &Path, id: u64) {
+        self.files.insert(Self::key(key), id);
+    }
+
+    /// 统一使用 `/` 分隔，使清单在不同系统间通用
+    fn key(path: &Path) -> String {
+        path.iter()
+            .map(|v| v.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_key() {
+        let mut manifest = Manifest::default();
+        manifest.insert(&Path::new("List").join("Song - A"), 42);
+        assert_eq!(manifest.get(Path::new("List/Song - A")), Some(42));
+        assert_eq!(manifest.get(Path::new("Song - A")), None);
+        assert_eq!(
+            manifest.files_of(42).collect::<Vec<_>>(),
+            vec![PathBuf::from("List/Song - A")]
+        );
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert_eq!(json, r#"{"files":{"List/Song - A":42}}"#);
+    }
+
+    #[test]
+    fn test_find_manifest() {
+        let dir = std::env::temp_dir().join("ncmd-manifest");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("List/Album")).unwrap();
+        let mut manifest = Manifest::default();
+        manifest.insert(Path::new("List/Album/Song - A"), 42);
+        manifest.save(&dir).unwrap();
+        let (found, prefix) = Manifest::find(&dir.join("List/Album")).unwrap();
+        assert_eq!(prefix, Path::new("List/Album"));
+        assert_eq!(found.get(&prefix.join("Song - A")), Some(42));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,12 +1,28 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use lofty::{
-    config::WriteOptions,
-    file::{AudioFile, TaggedFileExt},
-    picture::{MimeType, Picture, PictureType},
+    TextEncoding,
+    config::{ParseOptions, WriteOptions},
+    file::{AudioFile, FileType, TaggedFileExt},
+    id3::v2::{
+        BinaryFrame, Frame, FrameId, Id3v2Tag, SyncTextContentType, SynchronizedTextFrame,
+        TimestampFormat,
+    },
+    mp4::{Atom, AtomData, AtomIdent, Ilst, Mp4File},
+    mpeg::MpegFile,
+    ogg::VorbisComments,
+    picture::{Picture, PictureInformation, PictureType},
     probe::Probe,
-    tag::{ItemKey, ItemValue, Tag, TagItem, TagType},
+    tag::{ItemKey, ItemValue, Tag, TagExt, TagItem, TagType},
 };
-use std::path::Path;
+use std::{borrow::Cow, fs::File, path::Path};
+
+use crate::{cover::Cover, lyrics};
+
+/// 记录歌曲Id和专辑Id的字段：ID3v2的TXXX描述、Vorbis注释和APEv2的键、MP4自定义atom的名称
+const SONG_ID_FIELD: &str = "NCM_SONG_ID";
+const ALBUM_ID_FIELD: &str = "NCM_ALBUM_ID";
+/// MP4自定义atom使用的命名空间
+const MP4_FREEFORM_MEAN: &str = "com.apple.iTunes";
 
 pub struct TrackInfo<'a> {
     /// Title of the track
@@ -15,29 +31,219 @@ pub struct TrackInfo<'a> {
     pub artists: &'a Vec<&'a str>,
     /// Album name
     pub album: &'a str,
-    /// Cover art image data (JPEG or PNG)
-    pub cover_data: &'a [u8],
-    /// MIME type of the cover image (typically "image/jpeg" or "image/png")
-    pub cover_mime_type: MimeType,
+    /// Album artist
+    pub album_artist: Option<&'a str>,
+    /// Track number and total number of tracks in the album
+    pub track: (Option<u32>, Option<u32>),
+    /// Disc number and total number of discs in the album
+    pub disc: (Option<u32>, Option<u32>),
+    /// Release date in `YYYY-MM-DD` format
+    pub release_date: Option<&'a str>,
+    pub genre: Option<&'a str>,
+    pub composer: Option<&'a str>,
+    pub lyricist: Option<&'a str>,
+    /// NetEase song ID
+    pub song_id: u64,
+    /// NetEase album ID
+    pub album_id: Option<u64>,
+    /// Lyrics in LRC format, embedded when present
+    pub lyrics: Option<&'a str>,
+    /// Front cover (JPEG or PNG)
+    pub cover: Option<&'a Cover>,
+}
+
+impl TrackInfo<'_> {
+    /// 将写入的文本标签，顺序与 [`read_fields`] 相同，不写入的标签为空字符串
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let number = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_default();
+        let text = |v: Option<&str>| v.unwrap_or_default().to_string();
+        let values = [
+            self.title.to_string(),
+            self.artists.join("; "),
+            self.album.to_string(),
+            text(self.album_artist),
+            number(self.track.0),
+            number(self.track.1),
+            number(self.disc.0),
+            number(self.disc.1),
+            text(self.release_date),
+            text(self.genre),
+            text(self.composer),
+            text(self.lyricist),
+        ];
+        let mut fields: Vec<(&'static str, String)> = field_keys()
+            .into_iter()
+            .zip(values)
+            .map(|((name, _), value)| (name, value))
+            .collect();
+        fields.push(("song_id", self.song_id.to_string()));
+        fields.push((
+            "album_id",
+            self.album_id.map(|v| v.to_string()).unwrap_or_default(),
+        ));
+        fields
+    }
 }
 
-pub fn write_metadata(extension: &str, path: &Path, info: &TrackInfo) -> Result<()> {
-    let tag_type = match extension {
-        "mp3" => TagType::Id3v2,
-        "flac" => TagType::VorbisComments,
-        _ => {
-            return Ok(());
+/// 比较标签时使用的字段名和对应的标签，歌曲Id和专辑Id另外读取
+fn field_keys() -> [(&'static str, ItemKey); 12] {
+    [
+        ("title", ItemKey::TrackTitle),
+        ("artist", ItemKey::TrackArtist),
+        ("album", ItemKey::AlbumTitle),
+        ("album_artist", ItemKey::AlbumArtist),
+        ("track", ItemKey::TrackNumber),
+        ("track_total", ItemKey::TrackTotal),
+        ("disc", ItemKey::DiscNumber),
+        ("disc_total", ItemKey::DiscTotal),
+        ("date", ItemKey::RecordingDate),
+        ("genre", ItemKey::Genre),
+        ("composer", ItemKey::Composer),
+        ("lyricist", ItemKey::Lyricist),
+    ]
+}
+
+/// 通过文件内容识别出的音频格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    file_type: FileType,
+}
+
+impl AudioFormat {
+    /// 该格式常用的扩展名
+    pub fn extension(&self) -> &'static str {
+        match self.file_type {
+            FileType::Aac => "aac",
+            FileType::Aiff => "aiff",
+            FileType::Ape => "ape",
+            FileType::Flac => "flac",
+            FileType::Mpeg => "mp3",
+            FileType::Mp4 => "m4a",
+            FileType::Mpc => "mpc",
+            FileType::Opus => "opus",
+            FileType::Vorbis => "ogg",
+            FileType::Speex => "spx",
+            FileType::Wav => "wav",
+            FileType::WavPack => "wv",
+            _ => "bin",
         }
+    }
+
+    /// 是否支持写入标签和封面
+    pub fn taggable(&self) -> bool {
+        matches!(
+            self.file_type,
+            FileType::Mpeg
+                | FileType::Flac
+                | FileType::Vorbis
+                | FileType::Opus
+                | FileType::Speex
+                | FileType::Mp4
+                | FileType::Ape
+                | FileType::WavPack
+                | FileType::Mpc
+        )
+    }
+}
+
+/// 读取文件头识别实际的音频格式，不依赖扩展名
+pub fn probe_format(path: &Path) -> Result<AudioFormat> {
+    let Some(file_type) = Probe::open(path)?.guess_file_type()?.file_type() else {
+        bail!("Unknown audio format");
     };
-    let mut tagged_file = Probe::open(path)?.read()?;
-    let tag = match tagged_file.primary_tag_mut() {
-        Some(tag) => tag,
-        None => {
-            // 如果文件还没有标签，创建一个新的 ID3v2 标签
-            &mut tagged_file.insert_tag(Tag::new(tag_type)).unwrap()
+    Ok(AudioFormat { file_type })
+}
+
+/// 读取文件中已有的文本标签，多个值用 `; ` 连接，没有的标签为空字符串
+pub fn read_fields(path: &Path) -> Result<Vec<(&'static str, String)>> {
+    let format = probe_format(path)?;
+    if !format.taggable() {
+        bail!("Unsupported format: {}", format.extension());
+    }
+    let tagged_file = Probe::open(path)?.guess_file_type()?.read()?;
+    let tag = tagged_file.tag(format.file_type.primary_tag_type());
+    let mut fields: Vec<(&'static str, String)> = field_keys()
+        .into_iter()
+        .map(|(name, key)| {
+            let value = tag
+                .map(|tag| tag.get_strings(&key).collect::<Vec<_>>().join("; "))
+                .unwrap_or_default();
+            (name, value)
+        })
+        .collect();
+    for (name, field) in [("song_id", SONG_ID_FIELD), ("album_id", ALBUM_ID_FIELD)] {
+        let value = read_id_field(path, format, tag, field)?
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        fields.push((name, value));
+    }
+    Ok(fields)
+}
+
+/// 读取文件标签中记录的歌曲Id，无法读取时返回 `None`
+pub fn read_song_id(path: &Path) -> Option<u64> {
+    let fields = read_fields(path).ok()?;
+    fields
+        .iter()
+        .find(|(key, _)| *key == "song_id")
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+/// 读取记录Id的字段，ID3v2的TXXX和MP4的自定义atom不在通用标签中，需要按格式单独读取
+fn read_id_field(
+    path: &Path,
+    format: AudioFormat,
+    tag: Option<&Tag>,
+    field: &str,
+) -> Result<Option<u64>> {
+    let value = match format.file_type {
+        FileType::Mpeg => {
+            let file = MpegFile::read_from(&mut File::open(path)?, ParseOptions::new())?;
+            file.id3v2()
+                .and_then(|tag| tag.get_user_text(field))
+                .map(str::to_string)
         }
+        FileType::Mp4 => {
+            let file = Mp4File::read_from(&mut File::open(path)?, ParseOptions::new())?;
+            file.ilst()
+                .and_then(|tag| tag.get(&freeform_ident(field)))
+                .and_then(|atom| {
+                    atom.data().find_map(|data| match data {
+                        AtomData::UTF8(v) => Some(v.clone()),
+                        _ => None,
+                    })
+                })
+        }
+        _ => tag
+            .and_then(|tag| tag.get_string(&ItemKey::Unknown(field.to_string())))
+            .map(str::to_string),
     };
+    Ok(value.and_then(|v| v.trim().parse().ok()))
+}
+
+fn freeform_ident(name: &str) -> AtomIdent<'_> {
+    AtomIdent::Freeform {
+        mean: Cow::Borrowed(MP4_FREEFORM_MEAN),
+        name: Cow::Borrowed(name),
+    }
+}
+
+/// 写入标签、封面和歌词，格式由文件内容识别
+///
+/// 支持mp3(ID3v2)、flac/ogg/opus(Vorbis注释)、m4a(MP4 ilst)和ape/wv/mpc(APEv2)，其它格式返回错误
+pub fn write_metadata(path: &Path, info: &TrackInfo) -> Result<()> {
+    let format = probe_format(path)?;
+    if !format.taggable() {
+        bail!("Unsupported format: {}", format.extension());
+    }
+    let tag_type = format.file_type.primary_tag_type();
+    let tagged_file = Probe::open(path)?.guess_file_type()?.read()?;
+    let mut tag = tagged_file
+        .tag(tag_type)
+        .cloned()
+        .unwrap_or_else(|| Tag::new(tag_type));
     tag.insert_text(ItemKey::TrackTitle, info.title.to_string());
+    tag.retain(|item| !matches!(item.key(), ItemKey::TrackArtist));
     for artist in info.artists {
         tag.push(TagItem::new(
             ItemKey::TrackArtist,
@@ -45,11 +251,125 @@ pub fn write_metadata(extension: &str, path: &Path, info: &TrackInfo) -> Result<
         ));
     }
     tag.insert_text(ItemKey::AlbumTitle, info.album.to_string());
-    let picture = Picture::unchecked(info.cover_data.to_vec())
-        .mime_type(info.cover_mime_type.clone())
+    let optional_texts = [
+        (ItemKey::AlbumArtist, info.album_artist.map(str::to_string)),
+        (ItemKey::TrackNumber, info.track.0.map(|v| v.to_string())),
+        (ItemKey::TrackTotal, info.track.1.map(|v| v.to_string())),
+        (ItemKey::DiscNumber, info.disc.0.map(|v| v.to_string())),
+        (ItemKey::DiscTotal, info.disc.1.map(|v| v.to_string())),
+        (
+            ItemKey::RecordingDate,
+            info.release_date.map(str::to_string),
+        ),
+        (ItemKey::Genre, info.genre.map(str::to_string)),
+        (ItemKey::Composer, info.composer.map(str::to_string)),
+        (ItemKey::Lyricist, info.lyricist.map(str::to_string)),
+    ];
+    for (key, value) in optional_texts {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            tag.insert_text(key, value);
+        }
+    }
+    let ids = [
+        (SONG_ID_FIELD, Some(info.song_id)),
+        (ALBUM_ID_FIELD, info.album_id),
+    ];
+    if !matches!(tag_type, TagType::Id3v2 | TagType::Mp4Ilst) {
+        for (field, id) in ids {
+            if let Some(id) = id {
+                tag.insert_text(ItemKey::Unknown(field.to_string()), id.to_string());
+            }
+        }
+    }
+    // Vorbis注释的封面需要带上尺寸信息，在转换后单独写入
+    if let Some(cover) = info.cover
+        && tag_type != TagType::VorbisComments
+    {
+        tag.set_picture(0, front_cover(cover));
+    }
+    let lrc = info.lyrics.filter(|v| !v.trim().is_empty());
+    if let Some(lrc) = lrc {
+        // ID3v2的USLT只放纯文本，其它格式保留时间标签
+        let text = match tag_type {
+            TagType::Id3v2 => lyrics::plain_text(lrc),
+            _ => lrc.to_string(),
+        };
+        tag.insert_text(ItemKey::Lyrics, text);
+    }
+    match tag_type {
+        TagType::Id3v2 => {
+            let mut tag = Id3v2Tag::from(tag);
+            if let Some(lrc) = lrc {
+                insert_synced_lyrics(&mut tag, lrc)?;
+            }
+            for (field, id) in ids {
+                if let Some(id) = id {
+                    tag.insert_user_text(field.to_string(), id.to_string());
+                }
+            }
+            tag.save_to_path(path, WriteOptions::default())?;
+        }
+        TagType::Mp4Ilst => {
+            let mut tag = Ilst::from(tag);
+            for (field, id) in ids {
+                if let Some(id) = id {
+                    tag.replace_atom(Atom::new(
+                        freeform_ident(field),
+                        AtomData::UTF8(id.to_string()),
+                    ));
+                }
+            }
+            tag.save_to_path(path, WriteOptions::default())?;
+        }
+        TagType::VorbisComments => {
+            let mut tag = VorbisComments::from(tag);
+            if let Some(cover) = info.cover {
+                tag.remove_picture_type(PictureType::CoverFront);
+                let picture_info = PictureInformation {
+                    width: cover.width,
+                    height: cover.height,
+                    color_depth: cover.color_depth,
+                    num_colors: 0,
+                };
+                tag.insert_picture(front_cover(cover), Some(picture_info))?;
+            }
+            if let Some(lrc) = lrc {
+                tag.insert(String::from("UNSYNCEDLYRICS"), lyrics::plain_text(lrc));
+            }
+            tag.save_to_path(path, WriteOptions::default())?;
+        }
+        _ => tag.save_to_path(path, WriteOptions::default())?,
+    }
+    Ok(())
+}
+
+fn front_cover(cover: &Cover) -> Picture {
+    Picture::unchecked(cover.data.clone())
+        .mime_type(cover.mime_type.clone())
         .pic_type(PictureType::CoverFront)
-        .build();
-    tag.set_picture(0, picture);
-    tagged_file.save_to_path(path, WriteOptions::default())?;
+        .build()
+}
+
+/// 加入ID3v2的SYLT(逐行时间)歌词
+fn insert_synced_lyrics(tag: &mut Id3v2Tag, lrc: &str) -> Result<()> {
+    let lines: Vec<(u32, String)> = lyrics::parse_synced(lrc)
+        .into_iter()
+        .map(|v| (v.time, v.text))
+        .collect();
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let frame = SynchronizedTextFrame::new(
+        TextEncoding::UTF8,
+        *b"XXX",
+        TimestampFormat::MS,
+        SyncTextContentType::Lyrics,
+        None,
+        lines,
+    );
+    tag.insert(Frame::Binary(BinaryFrame::new(
+        FrameId::Valid(Cow::Borrowed("SYLT")),
+        frame.as_bytes()?,
+    )));
     Ok(())
 }
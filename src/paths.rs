@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+
+use crate::{cli::Args, config::ConfigSource};
+
+const APP_NAME: &str = "ncmdownloader";
+const CONFIG_FILE_NAME: &str = "config.yml";
+const COOKIE_FILE_NAME: &str = "cookie.json";
+
+/// 程序使用的文件路径
+#[derive(Debug)]
+pub struct Paths {
+    /// 配置文件路径，找不到任何配置文件时在此生成默认配置
+    pub config: PathBuf,
+    /// `--config` 指定的配置文件
+    custom_config: Option<PathBuf>,
+    /// cookie文件路径
+    pub cookie: PathBuf,
+    /// 歌单文件夹所在的目录
+    pub output_dir: PathBuf,
+}
+
+impl Paths {
+    /// 按 命令行参数/环境变量 > 当前目录下已有的文件 > 系统配置/数据目录 的顺序确定路径
+    pub fn resolve(args: &Args) -> Self {
+        Self {
+            config: args
+                .config
+                .clone()
+                .unwrap_or_else(|| default_path(CONFIG_FILE_NAME, dirs::config_dir())),
+            custom_config: args.config.clone(),
+            cookie: args
+                .cookie
+                .clone()
+                .unwrap_or_else(|| default_path(COOKIE_FILE_NAME, dirs::data_dir())),
+            output_dir: args
+                .output_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(".")),
+        }
+    }
+}
+
+impl Paths {
+    /// 按优先级从低到高列出需要合并的配置文件
+    pub fn config_files(&self) -> Vec<(ConfigSource, PathBuf)> {
+        let mut files = Vec::new();
+        if let Some(dir) = system_config_dir() {
+            let path = dir.join(APP_NAME).join(CONFIG_FILE_NAME);
+            files.push((ConfigSource::System(path.clone()), path));
+        }
+        if let Some(dir) = dirs::config_dir() {
+            let path = dir.join(APP_NAME).join(CONFIG_FILE_NAME);
+            files.push((ConfigSource::User(path.clone()), path));
+        }
+        let local = PathBuf::from(CONFIG_FILE_NAME);
+        files.push((ConfigSource::Local(local.clone()), local));
+        if let Some(path) = &self.custom_config {
+            files.push((ConfigSource::Custom(path.clone()), path.clone()));
+        }
+        files
+    }
+}
+
+#[cfg(unix)]
+fn system_config_dir() -> Option<PathBuf> {
+    Some(PathBuf::from("/etc"))
+}
+
+#[cfg(windows)]
+fn system_config_dir() -> Option<PathBuf> {
+    std::env::var_os("ProgramData").map(PathBuf::from)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn system_config_dir() -> Option<PathBuf> {
+    None
+}
+
+/// 当前目录下已有该文件时沿用旧的位置，否则放到系统目录下
+fn default_path(file_name: &str, system_dir: Option<PathBuf>) -> PathBuf {
+    let local = PathBuf::from(file_name);
+    if local.exists() {
+        return local;
+    }
+    match system_dir {
+        Some(dir) => dir.join(APP_NAME).join(file_name),
+        None => local,
+    }
+}
+
+/// 创建文件所在的目录
+pub fn ensure_parent(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => std::fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
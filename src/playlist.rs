@@ -0,0 +1,248 @@
+//! 按歌单原有顺序生成播放列表文件
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::track::Track;
+
+/// 查找之前下载的文件时识别的音频扩展名
+pub const AUDIO_EXTENSIONS: [&str; 12] = [
+    "mp3", "flac", "m4a", "ogg", "opus", "ape", "wav", "aac", "aiff", "spx", "wv", "mpc",
+];
+
+/// 播放列表格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+}
+
+/// 下载失败的歌曲在播放列表中的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailedTracks {
+    /// 不写入
+    #[default]
+    Omit,
+    /// 以注释的形式写入
+    Comment,
+}
+
+/// 播放列表中的一首歌曲
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: u64,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: String,
+    /// 时长(毫秒)
+    pub duration: u64,
+    /// 相对于播放列表文件的路径，没有文件时为 `None`
+    pub path: Option<PathBuf>,
+}
+
+impl Entry {
+    pub fn new(track: &Track) -> Self {
+        Self {
+            id: track.id,
+            title: track.title.clone(),
+            artists: track.artists.clone(),
+            album: track.album.clone(),
+            duration: track.duration,
+            path: None,
+        }
+    }
+
+    fn display_name(&self) -> String {
+        format!("{} - {}", self.artists.join(", "), self.title)
+    }
+}
+
+/// 生成播放列表文件，已有的文件会被覆盖
+pub fn write(
+    path: &Path,
+    format: PlaylistFormat,
+    name: &str,
+    entries: &[Entry],
+    failed: FailedTracks,
+) -> Result<()> {
+    let content = match format {
+        PlaylistFormat::M3u8 => to_m3u8(name, entries, failed),
+        PlaylistFormat::Xspf => to_xspf(name, entries, failed),
+    };
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to write playlist {}", path.display()))
+}
+
+/// 在文件夹中查找之前下载的音频文件，`stem` 为不含扩展名的路径
+pub fn find_audio(output_dir: &Path, stem: &Path) -> Option<PathBuf> {
+    let file_name = stem.file_name()?.to_string_lossy();
+    let candidates = [file_name.to_string(), format!("{} [preview]", file_name)];
+    candidates.iter().find_map(|name| {
+        AUDIO_EXTENSIONS.iter().find_map(|ext| {
+            let path = stem.with_file_name(format!("{}.{}", name, ext));
+            output_dir.join(&path).is_file().then_some(path)
+        })
+    })
+}
+
+fn to_m3u8(name: &str, entries: &[Entry], failed: FailedTracks) -> String {
+    let mut content = String::from("#EXTM3U\n");
+    let _ = writeln!(content, "#PLAYLIST:{}", single_line(name));
+    for entry in entries {
+        match &entry.path {
+            Some(path) => {
+                let _ = writeln!(
+                    content,
+                    "#EXTINF:{},{}",
+                    entry.duration / 1000,
+                    single_line(&entry.display_name())
+                );
+                let _ = writeln!(content, "{}", path_to_string(path));
+            }
+            None if failed == FailedTracks::Comment => {
+                let _ = writeln!(content, "# 未下载：{}", single_line(&entry.display_name()));
+            }
+            None => {}
+        }
+    }
+    content
+}
+
+fn to_xspf(name: &str, entries: &[Entry], failed: FailedTracks) -> String {
+    let mut content = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    let _ = writeln!(content, "  <title>{}</title>", escape_xml(name));
+    content.push_str("  <trackList>\n");
+    for entry in entries {
+        match &entry.path {
+            Some(path) => {
+                content.push_str("    <track>\n");
+                let _ = writeln!(
+                    content,
+                    "      <location>{}</location>",
+                    escape_xml(&encode_uri(&path_to_string(path)))
+                );
+                let _ = writeln!(content, "      <title>{}</title>", escape_xml(&entry.title));
+                let _ = writeln!(
+                    content,
+                    "      <creator>{}</creator>",
+                    escape_xml(&entry.artists.join(", "))
+                );
+                if !entry.album.is_empty() {
+                    let _ = writeln!(content, "      <album>{}</album>", escape_xml(&entry.album));
+                }
+                if entry.duration > 0 {
+                    let _ = writeln!(content, "      <duration>{}</duration>", entry.duration);
+                }
+                content.push_str("    </track>\n");
+            }
+            None if failed == FailedTracks::Comment => {
+                // 注释中不能出现 `--`
+                let _ = writeln!(
+                    content,
+                    "    <!-- 未下载：{} -->",
+                    escape_xml(&entry.display_name()).replace("--", "- -")
+                );
+            }
+            None => {}
+        }
+    }
+    content.push_str("  </trackList>\n</playlist>\n");
+    content
+}
+
+/// 播放列表中统一使用 `/` 分隔路径
+fn path_to_string(path: &Path) -> String {
+    path.iter()
+        .map(|v| v.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn single_line(s: &str) -> String {
+    s.replace(['\r', '\n'], " ")
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// 对相对路径进行百分号编码，保留 `/`
+fn encode_uri(path: &str) -> String {
+    let mut result = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            result.push(byte as char);
+        } else {
+            let _ = write!(result, "%{:02X}", byte);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        let track = Track {
+            title: "Song & Dance".to_string(),
+            artists: vec!["A".to_string(), "B".to_string()],
+            album: "Album".to_string(),
+            duration: 245_500,
+            ..Default::default()
+        };
+        let mut downloaded = Entry::new(&track);
+        downloaded.path = Some(PathBuf::from("List").join("歌 1.flac"));
+        let mut failed = Entry::new(&track);
+        failed.title = "Missing".to_string();
+        vec![downloaded, failed]
+    }
+
+    #[test]
+    fn test_m3u8() {
+        assert_eq!(
+            to_m3u8("List", &entries(), FailedTracks::Omit),
+            "#EXTM3U\n#PLAYLIST:List\n#EXTINF:245,A, B - Song & Dance\nList/歌 1.flac\n"
+        );
+        assert!(
+            to_m3u8("List", &entries(), FailedTracks::Comment)
+                .ends_with("# 未下载：A, B - Missing\n")
+        );
+    }
+
+    #[test]
+    fn test_xspf() {
+        let content = to_xspf("List", &entries(), FailedTracks::Comment);
+        assert!(content.contains("<location>List/%E6%AD%8C%201.flac</location>"));
+        assert!(content.contains("<title>Song &amp; Dance</title>"));
+        assert!(content.contains("<duration>245500</duration>"));
+        assert!(content.contains("<!-- 未下载：A, B - Missing -->"));
+    }
+}
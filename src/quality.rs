@@ -0,0 +1,170 @@
+use anyhow::Result;
+
+use crate::{
+    api::{ExtraApi, SongUrl},
+    config::{fidelity_rank, normalize_level},
+};
+
+/// 服务器没有返回音质时，按码率判断各音质的最低码率(bps)
+const LEVEL_MIN_BITRATES: [(&str, u64); 3] = [
+    ("standard", 128_000),
+    ("higher", 192_000),
+    ("exhigh", 320_000),
+];
+/// 无损及以上音质的最低码率(bps)
+const LOSSLESS_MIN_BITRATE: u64 = 400_000;
+/// 时长或大小低于完整歌曲的该比例时视为试听片段
+const PREVIEW_RATIO: f64 = 0.5;
+
+/// 按音质偏好依次尝试获取下载链接
+///
+/// 服务器在没有请求的音质时会返回较低的音质，如果返回的音质也在偏好列表中则直接使用；
+/// 都没有匹配时使用第一个有效的链接。试听片段只在所有音质都没有完整歌曲时使用，
+/// 所有音质都没有链接时返回 `None`
+pub async fn select_song_url(
+    api: &ExtraApi,
+    id: u64,
+    duration_ms: u64,
+    levels: &[&str],
+) -> Result<Option<SongUrl>> {
+    let mut fallback: Option<SongUrl> = None;
+    let mut preview: Option<SongUrl> = None;
+    let mut last_error = None;
+    let mut responded = false;
+    for (index, level) in levels.iter().enumerate() {
+        let song_url = match api.song_url(id, level).await {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Failed to get {} url for song {}: {}", level, id, e);
+                last_error = Some(e);
+                continue;
+            }
+        };
+        responded = true;
+        if song_url.url.as_deref().unwrap_or_default().is_empty() {
+            continue;
+        }
+        if is_preview(&song_url, duration_ms) {
+            if preview.is_none() {
+                preview = Some(song_url);
+            }
+            continue;
+        }
+        let accepted = match song_url.level.as_deref() {
+            Some(obtained) => levels[index..].contains(&obtained),
+            None => true,
+        };
+        if accepted {
+            return Ok(Some(song_url));
+        }
+        if fallback.is_none() {
+            fallback = Some(song_url);
+        }
+    }
+    match (fallback.or(preview), last_error) {
+        (Some(song_url), _) => Ok(Some(song_url)),
+        (None, Some(e)) if !responded => Err(e),
+        _ => Ok(None),
+    }
+}
+
+/// 判断实际获取到的音质是否不低于最低音质，环绕声等音效音质不满足无损及以上的最低音质
+pub fn meets_min_level(song_url: &SongUrl, min_level: &str) -> bool {
+    // 兼容旧版本配置中的拼写错误等别名
+    let Some(min_level) = normalize_level(min_level) else {
+        return true;
+    };
+    let Some(min_rank) = fidelity_rank(min_level) else {
+        return true;
+    };
+    match song_url.level.as_deref().and_then(fidelity_rank) {
+        Some(rank) => rank >= min_rank,
+        None => {
+            let min_bitrate = LEVEL_MIN_BITRATES
+                .iter()
+                .find(|(level, _)| *level == min_level)
+                .map(|(_, bitrate)| *bitrate)
+                .unwrap_or(LOSSLESS_MIN_BITRATE);
+            song_url.br >= min_bitrate
+        }
+    }
+}
+
+/// 判断下载链接是否只是试听片段
+///
+/// 服务器返回了试听信息，或者文件时长、大小远小于歌曲时长对应的值时视为试听片段
+pub fn is_preview(song_url: &SongUrl, duration_ms: u64) -> bool {
+    if song_url.free_trial_info.is_some() {
+        return true;
+    }
+    if duration_ms == 0 {
+        return false;
+    }
+    let threshold = duration_ms as f64 * PREVIEW_RATIO;
+    if song_url.time > 0 && (song_url.time as f64) < threshold {
+        return true;
+    }
+    if song_url.size > 0 && song_url.br > 0 {
+        let expected_size = threshold / 1000.0 * song_url.br as f64 / 8.0;
+        return (song_url.size as f64) < expected_size;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song_url(level: Option<&str>, br: u64, size: u64, time: u64) -> SongUrl {
+        SongUrl {
+            url: Some("https://example.com/1.mp3".to_string()),
+            br,
+            size,
+            extension: Some("mp3".to_string()),
+            level: level.map(str::to_string),
+            time,
+            free_trial_info: None,
+        }
+    }
+
+    #[test]
+    fn test_meets_min_level() {
+        assert!(meets_min_level(
+            &song_url(Some("lossless"), 900_000, 0, 0),
+            "exhigh"
+        ));
+        assert!(!meets_min_level(
+            &song_url(Some("exhigh"), 320_000, 0, 0),
+            "lossless"
+        ));
+        assert!(!meets_min_level(&song_url(None, 128_000, 0, 0), "exhigh"));
+        assert!(meets_min_level(&song_url(None, 320_000, 0, 0), "exhigh"));
+        assert!(meets_min_level(&song_url(None, 192_000, 0, 0), "higer"));
+        assert!(!meets_min_level(&song_url(None, 128_000, 0, 0), "higer"));
+        assert!(!meets_min_level(
+            &song_url(Some("dolby"), 768_000, 0, 0),
+            "lossless"
+        ));
+        assert!(meets_min_level(
+            &song_url(Some("sky"), 768_000, 0, 0),
+            "exhigh"
+        ));
+        assert!(meets_min_level(
+            &song_url(Some("jymaster"), 4_000_000, 0, 0),
+            "hires"
+        ));
+    }
+
+    #[test]
+    fn test_is_preview() {
+        let full = song_url(Some("exhigh"), 320_000, 9_600_000, 240_000);
+        assert!(!is_preview(&full, 240_000));
+        let clip = song_url(Some("exhigh"), 320_000, 1_200_000, 30_000);
+        assert!(is_preview(&clip, 240_000));
+        let clip = song_url(Some("exhigh"), 320_000, 1_200_000, 0);
+        assert!(is_preview(&clip, 240_000));
+        let mut trial = full.clone();
+        trial.free_trial_info = Some(serde_json::json!({ "start": 0, "end": 30 }));
+        assert!(is_preview(&trial, 240_000));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    cli,
+    config::{Config, JobMode, PreviewPolicy},
+};
+
+/// 下载结果汇总，下载完成后统一输出
+#[derive(Debug, Default)]
+pub struct Report {
+    pub failed_songs: Vec<String>,
+    pub failed_lyrics: Vec<String>,
+    /// 游客模式下需要登录才能下载的歌曲，包括只能获取试听片段的歌曲
+    pub unavailable_songs: Vec<String>,
+    /// 歌曲及实际获取到的音质
+    pub obtained_levels: Vec<(String, String)>,
+    /// 音质低于最低音质而跳过的歌曲
+    pub below_min_skipped: Vec<String>,
+    /// 音质低于最低音质但仍然下载的歌曲
+    pub below_min_flagged: Vec<String>,
+    /// 只能获取试听片段的歌曲
+    pub preview_songs: Vec<String>,
+    /// 格式不支持或写入失败而没有标签的歌曲
+    pub untagged_songs: Vec<String>,
+    /// 补全歌词时没有找到已下载文件的歌曲
+    pub missing_audio_songs: Vec<String>,
+    /// 补全歌词时新保存了歌词的歌曲数
+    pub completed_lyrics: usize,
+}
+
+impl Report {
+    /// `preferred_level` 为用户指定的首选音质，使用 `auto` 时为 `None`，不列出未获取首选音质的歌曲
+    pub async fn print(&self, config: &Config, preferred_level: Option<&str>) {
+        if !self.failed_songs.is_empty() {
+            let _ = cli::print(&format!("歌曲下载失败：{}", self.failed_songs.join(", "))).await;
+        }
+        if !self.failed_lyrics.is_empty() {
+            let _ = cli::print(&format!("歌词下载失败：{}", self.failed_lyrics.join(", "))).await;
+        }
+        if !self.obtained_levels.is_empty() {
+            let mut counts: Vec<(&str, usize)> = Vec::new();
+            for (_, level) in self.obtained_levels.iter() {
+                match counts.iter_mut().find(|(v, _)| *v == level.as_str()) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((level.as_str(), 1)),
+                }
+            }
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(level, count)| format!("{} {}首", level, count))
+                .collect();
+            let _ = cli::print(&format!("获取到的音质：{}", counts.join(", "))).await;
+        }
+        if let Some(preferred_level) = preferred_level
+            && !self.obtained_levels.is_empty()
+        {
+            let fallback: Vec<String> = self
+                .obtained_levels
+                .iter()
+                .filter(|(_, level)| level != preferred_level)
+                .map(|(name, level)| format!("{}({})", name, level))
+                .collect();
+            if !fallback.is_empty() {
+                let _ = cli::print(&format!(
+                    "以下歌曲未能获取首选音质 {}：{}",
+                    preferred_level,
+                    fallback.join(", ")
+                ))
+                .await;
+            }
+        }
+        if let Some(min_level) = config.min_bitrate_level() {
+            if !self.below_min_skipped.is_empty() {
+                let _ = cli::print(&format!(
+                    "以下歌曲音质低于 {}，已跳过：{}",
+                    min_level,
+                    self.below_min_skipped.join(", ")
+                ))
+                .await;
+            }
+            if !self.below_min_flagged.is_empty() {
+                let _ = cli::print(&format!(
+                    "以下歌曲音质低于 {}，已降级下载：{}",
+                    min_level,
+                    self.below_min_flagged.join(", ")
+                ))
+                .await;
+            }
+        }
+        if !self.preview_songs.is_empty() {
+            let action = match config.preview_policy {
+                PreviewPolicy::Skip => "已跳过",
+                PreviewPolicy::Save => "已保存试听片段",
+            };
+            let _ = cli::print(&format!(
+                "以下歌曲只能获取试听片段，{}：{}",
+                action,
+                self.preview_songs.join(", ")
+            ))
+            .await;
+        }
+        if !self.untagged_songs.is_empty() {
+            let _ = cli::print(&format!(
+                "以下歌曲未能写入标签和封面：{}",
+                self.untagged_songs.join(", ")
+            ))
+            .await;
+        }
+        if config.mode == JobMode::Lyrics {
+            let _ = cli::print(&format!("已为 {} 首歌曲补全歌词", self.completed_lyrics)).await;
+        }
+        if !self.missing_audio_songs.is_empty() {
+            let _ = cli::print(&format!(
+                "以下歌曲没有找到已下载的文件，未保存歌词：{}",
+                self.missing_audio_songs.join(", ")
+            ))
+            .await;
+        }
+        if !self.unavailable_songs.is_empty() {
+            let _ = cli::print(&format!(
+                "以下歌曲需要登录后才能下载完整歌曲：{}",
+                self.unavailable_songs.join(", ")
+            ))
+            .await;
+        }
+    }
+}
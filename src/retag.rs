@@ -0,0 +1,414 @@
+//! 为已有的歌曲文件重新写入标签和封面，不修改音频数据
+use anyhow::{Result, bail};
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use crate::{
+    api::{ExtraApi, SongDetail},
+    cli,
+    config::Config,
+    cover::{Cover, CoverCache},
+    download::DownloadOptions,
+    library::find_audio_files,
+    lyrics::{self, Lrc},
+    manifest::Manifest,
+    metadata::{self, TrackInfo},
+    track::{AlbumCache, Track},
+};
+
+/// 按标题和歌手搜索时获取的结果数
+const SEARCH_LIMIT: usize = 10;
+const PREVIEW_SUFFIX: &str = " [preview]";
+
+/// 歌曲Id的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdSource {
+    /// 文件标签中的歌曲Id
+    Tag,
+    /// 输出目录中的清单
+    Manifest,
+    /// 按标题和歌手搜索，标题完全相同
+    Search,
+    /// 按标题和歌手搜索，只有标题的开头相同，只在预览时显示，不写入
+    PartialSearch,
+}
+
+impl IdSource {
+    fn description(&self) -> &'static str {
+        match self {
+            IdSource::Tag => "标签",
+            IdSource::Manifest => "清单",
+            IdSource::Search => "搜索",
+            IdSource::PartialSearch => "搜索，标题不完全相同",
+        }
+    }
+}
+
+/// 扫描文件夹中的歌曲文件，获取最新的歌曲信息并重新写入标签
+///
+/// `dry_run` 为 `true` 时只显示会修改的标签，不写入文件。搜索到的歌曲标题不完全相同时只在预览中显示
+pub async fn run(
+    dir: &Path,
+    dry_run: bool,
+    config: &Config,
+    extra_api: Arc<ExtraApi>,
+) -> Result<()> {
+    let files = {
+        let dir = dir.to_path_buf();
+        tokio::task::spawn_blocking(move || find_audio_files(&dir)).await?
+    };
+    if files.is_empty() {
+        bail!("{} 中没有找到歌曲文件", dir.display());
+    }
+    let _ = cli::print(&format!(
+        "找到 {} 个歌曲文件，正在查找对应的歌曲",
+        files.len()
+    ))
+    .await;
+    // 指定的可能是输出目录中的子文件夹，清单中的路径相对于清单所在的文件夹
+    let (manifest, manifest_prefix) = Manifest::find(dir).unwrap_or_default();
+    let mut resolved = Vec::new();
+    let mut not_found = Vec::new();
+    let mut failed = Vec::new();
+    for relative in files {
+        let path = dir.join(&relative);
+        let fields = match tokio::task::spawn_blocking(move || metadata::read_fields(&path))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|v| v)
+        {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Failed to read tags of {}: {}", relative.display(), e);
+                failed.push(relative.display().to_string());
+                continue;
+            }
+        };
+        match find_song_id(&relative, &fields, &manifest, &manifest_prefix, &extra_api).await {
+            // 标题不完全相同时可能不是同一首歌曲，需要先预览确认
+            Some((_, IdSource::PartialSearch)) if !dry_run => {
+                not_found.push(relative.display().to_string())
+            }
+            Some((id, source)) => resolved.push((relative, id, source, fields)),
+            None => not_found.push(relative.display().to_string()),
+        }
+    }
+    let ids: Vec<u64> = resolved.iter().map(|(_, id, _, _)| *id).collect();
+    let details: HashMap<u64, SongDetail> = match extra_api.songs_detail(&ids).await {
+        Ok(details) => details.into_iter().map(|v| (v.id, v)).collect(),
+        Err(e) => bail!("获取歌曲详情失败：{}", e),
+    };
+    let album_cache = AlbumCache::new(extra_api.clone());
+    let cover_cache = CoverCache::new(
+        config.cover_size,
+        DownloadOptions::new(config.retry, config.retry_delay, config.timeout),
+    );
+    let mut changed = 0;
+    for (relative, id, source, old_fields) in resolved {
+        let Some(detail) = details.get(&id) else {
+            not_found.push(relative.display().to_string());
+            continue;
+        };
+        let mut track = Track::from_detail(detail);
+        if let Some(album) = album_cache.get(track.album_id).await {
+            track.apply_album(&album);
+        }
+        let song_lyrics = match extra_api.lyrics(id).await {
+            Ok(v) => Some(v),
+            Err(e) => {
+                log::warn!("Failed to get lyric for {}: {}", id, e);
+                None
+            }
+        };
+        if let Some(song_lyrics) = &song_lyrics {
+            track.apply_lyric_credits(&song_lyrics.lyric);
+        }
+        // 分开保存的翻译不写入歌曲文件
+        let lyric = song_lyrics
+            .as_ref()
+            .map(|v| lyrics::compose(v, &config.lyrics_variants, config.lyrics_style).0)
+            .map(|v| Lrc::parse(&v, config.lyrics_credits).to_lrc())
+            .filter(|_| config.embed_lyrics);
+        if dry_run {
+            // 预览时不需要下载封面
+            let changes = with_track_info(&track, lyric.as_deref(), None, |info| {
+                diff(&old_fields, &info.fields())
+            });
+            if !changes.is_empty() {
+                changed += 1;
+                let mut lines = vec![format!(
+                    "{} (歌曲Id {}，来自{})",
+                    relative.display(),
+                    id,
+                    source.description()
+                )];
+                for (name, old, new) in changes {
+                    lines.push(format!("  {}: {:?} -> {:?}", name, old, new));
+                }
+                let _ = cli::print(&lines.join("\n")).await;
+            }
+            continue;
+        }
+        let cover = if track.cover_url.is_empty() {
+            None
+        } else {
+            cover_cache.get(&track.cover_url).await
+        };
+        let path = dir.join(&relative);
+        let result = tokio::task::spawn_blocking(move || {
+            with_track_info(&track, lyric.as_deref(), cover.as_deref(), |info| {
+                metadata::write_metadata(&path, info)
+            })
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|v| v);
+        match result {
+            Ok(_) => changed += 1,
+            Err(e) => {
+                log::warn!("Failed to write metadata for {}: {}", relative.display(), e);
+                failed.push(relative.display().to_string());
+            }
+        }
+    }
+    let _ = cli::print(&if dry_run {
+        format!("预览完成，{} 个文件的标签会被修改", changed)
+    } else {
+        format!("已为 {} 个文件重新写入标签", changed)
+    })
+    .await;
+    if !not_found.is_empty() {
+        let _ = cli::print(&format!(
+            "以下文件未找到对应的歌曲：\n{}",
+            not_found.join("\n")
+        ))
+        .await;
+    }
+    if !failed.is_empty() {
+        let _ = cli::print(&format!("以下文件读写标签失败：\n{}", failed.join("\n"))).await;
+    }
+    Ok(())
+}
+
+/// 依次通过标签中的歌曲Id、清单和搜索查找文件对应的歌曲
+async fn find_song_id(
+    relative: &Path,
+    fields: &[(&'static str, String)],
+    manifest: &Manifest,
+    manifest_prefix: &Path,
+    extra_api: &ExtraApi,
+) -> Option<(u64, IdSource)> {
+    if let Some(id) = field(fields, "song_id").and_then(|v| v.parse().ok()) {
+        return Some((id, IdSource::Tag));
+    }
+    // 清单中记录的是不含扩展名和试听标记的路径
+    let stem = relative.file_stem()?.to_string_lossy();
+    let stem = stem.strip_suffix(PREVIEW_SUFFIX).unwrap_or(&stem);
+    if let Some(id) = manifest.get(&manifest_prefix.join(relative.with_file_name(stem))) {
+        return Some((id, IdSource::Manifest));
+    }
+    let (title, artist) = match field(fields, "title") {
+        Some(title) => (
+            title.to_string(),
+            field(fields, "artist").map(str::to_string),
+        ),
+        None => guess_from_file_name(stem),
+    };
+    let keywords = match &artist {
+        Some(artist) => format!("{} {}", title, artist),
+        None => title.clone(),
+    };
+    let results = match extra_api.search_songs(&keywords, SEARCH_LIMIT).await {
+        Ok(v) => v,
+        Err(e) => {
+            log::warn!("Failed to search {}: {}", keywords, e);
+            return None;
+        }
+    };
+    best_match(&results, &title, artist.as_deref())
+}
+
+/// 用歌曲信息生成要写入的标签
+fn with_track_info<R>(
+    track: &Track,
+    lyric: Option<&str>,
+    cover: Option<&Cover>,
+    f: impl FnOnce(&TrackInfo) -> R,
+) -> R {
+    let release_date = track.release_date_string();
+    f(&TrackInfo {
+        title: &track.title,
+        artists: &track.artists.iter().map(|v| v.as_str()).collect(),
+        album: &track.album,
+        album_artist: track.album_artist.as_deref(),
+        track: (track.track_number, track.track_total),
+        disc: (track.disc_number, track.disc_total),
+        release_date: release_date.as_deref(),
+        genre: track.genre.as_deref(),
+        composer: track.composer.as_deref(),
+        lyricist: track.lyricist.as_deref(),
+        song_id: track.id,
+        album_id: Some(track.album_id).filter(|&v| v > 0),
+        lyrics: lyric,
+        cover,
+    })
+}
+
+fn field<'a>(fields: &'a [(&'static str, String)], name: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim())
+        .filter(|v| !v.is_empty())
+}
+
+/// 没有标签时按默认的 `标题 - 歌手` 格式从文件名中取出标题和歌手
+fn guess_from_file_name(stem: &str) -> (String, Option<String>) {
+    // 去掉重名时加上的 ` (序号)` 或 ` (歌曲Id)`
+    let stem = match stem.rsplit_once(" (") {
+        Some((rest, suffix))
+            if suffix
+                .strip_suffix(')')
+                .is_some_and(|v| !v.is_empty() && v.chars().all(|c| c.is_ascii_digit())) =>
+        {
+            rest
+        }
+        _ => stem,
+    };
+    match stem.rsplit_once(" - ") {
+        Some((title, artist)) => (title.trim().to_string(), Some(artist.trim().to_string())),
+        None => (stem.trim().to_string(), None),
+    }
+}
+
+/// 在搜索结果中选出标题和歌手都匹配的歌曲，标题完全相同的优先
+fn best_match(
+    results: &[SongDetail],
+    title: &str,
+    artist: Option<&str>,
+) -> Option<(u64, IdSource)> {
+    let title = normalize(title);
+    let artist = artist.map(normalize);
+    if title.is_empty() {
+        return None;
+    }
+    results
+        .iter()
+        .filter_map(|song| {
+            let name = normalize(&song.name);
+            let source = if name == title {
+                IdSource::Search
+            } else if !name.is_empty() && (title.starts_with(&name) || name.starts_with(&title)) {
+                IdSource::PartialSearch
+            } else {
+                return None;
+            };
+            if let Some(artist) = &artist
+                && !song
+                    .artists
+                    .iter()
+                    .map(|v| normalize(&v.name))
+                    .any(|v| !v.is_empty() && artist.contains(&v))
+            {
+                return None;
+            }
+            Some((song.id, source))
+        })
+        // 标题完全相同的优先，其次取搜索结果中靠前的
+        .rev()
+        .max_by_key(|(_, source)| *source == IdSource::Search)
+}
+
+/// 只保留字母和数字并转为小写，用于比较标题和歌手
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// 会被修改的标签：(字段, 原来的值, 新的值)，新的值为空的标签不会写入
+fn diff(
+    old: &[(&'static str, String)],
+    new: &[(&'static str, String)],
+) -> Vec<(&'static str, String, String)> {
+    new.iter()
+        .filter(|(_, value)| !value.is_empty())
+        .filter_map(|(name, value)| {
+            let old = old
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, v)| v.as_str())
+                .unwrap_or_default();
+            (old != value).then(|| (*name, old.to_string(), value.clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(id: u64, name: &str, artist: &str) -> SongDetail {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": name,
+            "ar": [{ "id": 1, "name": artist }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_guess_from_file_name() {
+        assert_eq!(
+            guess_from_file_name("Song(译名) - A, B (123)"),
+            ("Song(译名)".to_string(), Some("A, B".to_string()))
+        );
+        assert_eq!(
+            guess_from_file_name("Song (Live)"),
+            ("Song (Live)".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn test_best_match() {
+        let results = vec![
+            song(1, "Song (Live)", "A"),
+            song(2, "Song", "Other"),
+            song(3, "song", "A"),
+        ];
+        assert_eq!(
+            best_match(&results, "Song", Some("A, B")),
+            Some((3, IdSource::Search))
+        );
+        assert_eq!(
+            best_match(&results, "Song", None),
+            Some((2, IdSource::Search))
+        );
+        assert_eq!(
+            best_match(&results, "Song(译名)", Some("A")),
+            Some((3, IdSource::PartialSearch))
+        );
+        assert_eq!(
+            best_match(&results, "Song Live", Some("A")),
+            Some((1, IdSource::Search))
+        );
+        assert_eq!(best_match(&results, "Another", Some("A")), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![("title", "Old".to_string()), ("genre", "Pop".to_string())];
+        let new = vec![
+            ("title", "New".to_string()),
+            ("genre", String::new()),
+            ("album", "Album".to_string()),
+        ];
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                ("title", "Old".to_string(), "New".to_string()),
+                ("album", String::new(), "Album".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::{Context, Result, bail};
+use cookie_store::{CookieExpiration, CookieStore};
+use ncm_api::MusicApi;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+const COOKIE_URL: &str = "https://music.163.com/";
+const COOKIE_DOMAIN: &str = "163.com";
+const LOGIN_COOKIE_NAME: &str = "MUSIC_U";
+/// 原始cookie字符串没有过期时间，按一年保存
+const RAW_COOKIE_MAX_AGE: u64 = 365 * 24 * 60 * 60;
+
+/// 将登录状态保存到cookie文件
+pub fn save_cookie_jar(api: &MusicApi, cookie_path: &Path) -> Result<()> {
+    crate::paths::ensure_parent(cookie_path)
+        .with_context(|| format!("Failed to create directory for {}", cookie_path.display()))?;
+    let mut writer = std::fs::File::create(cookie_path)
+        .map(std::io::BufWriter::new)
+        .with_context(|| format!("Failed to create {}", cookie_path.display()))?;
+    let cookie_jar = api.cookie_jar();
+    let store = cookie_jar.lock().unwrap();
+    if cookie_store::serde::json::save(&store, &mut writer).is_err() {
+        bail!("Failed to write {}", cookie_path.display());
+    }
+    Ok(())
+}
+
+/// 读取cookie文件中保存的登录状态
+pub fn load_cookie_jar(cookie_path: &Path) -> Result<CookieStore> {
+    let reader = std::fs::File::open(cookie_path)
+        .map(std::io::BufReader::new)
+        .with_context(|| format!("Failed to open {}", cookie_path.display()))?;
+    match cookie_store::serde::json::load(reader) {
+        Ok(v) => Ok(v),
+        Err(e) => bail!("Failed to parse {}: {}", cookie_path.display(), e),
+    }
+}
+
+/// 删除cookie文件，删除前先用0覆盖文件内容
+pub fn remove_cookie_file(cookie_path: &Path) -> Result<()> {
+    use std::io::Write;
+    let len = std::fs::metadata(cookie_path)?.len() as usize;
+    {
+        let mut file = std::fs::OpenOptions::new().write(true).open(cookie_path)?;
+        file.write_all(&vec![0u8; len])?;
+        file.sync_all()?;
+    }
+    std::fs::remove_file(cookie_path)?;
+    Ok(())
+}
+
+/// 获取登录cookie的过期时间，会话cookie返回`None`
+pub fn login_cookie_expiry(api: &MusicApi) -> Option<String> {
+    let cookie_jar = api.cookie_jar();
+    let store = cookie_jar.lock().unwrap();
+    let cookie = store
+        .iter_unexpired()
+        .find(|cookie| cookie.name() == LOGIN_COOKIE_NAME)?;
+    match cookie.expires {
+        CookieExpiration::AtUtc(expires) => Some(format!(
+            "{} {:02}:{:02}:{:02} UTC",
+            expires.date(),
+            expires.hour(),
+            expires.minute(),
+            expires.second()
+        )),
+        CookieExpiration::SessionEnd => None,
+    }
+}
+
+/// 解析浏览器导出的cookie
+///
+/// 支持Netscape格式的`cookies.txt`，以及`MUSIC_U=...; __csrf=...`形式的原始cookie字符串。
+/// 只保留网易云音乐相关域名下的cookie，且必须包含`MUSIC_U`
+pub fn parse_cookies(content: &str) -> Result<CookieStore> {
+    let url = Url::parse(COOKIE_URL).unwrap();
+    let set_cookies = if is_netscape_format(content) {
+        parse_netscape(content)
+    } else {
+        parse_raw(content)
+    };
+    let mut store = CookieStore::default();
+    for set_cookie in set_cookies {
+        if let Err(e) = store.parse(&set_cookie, &url) {
+            log::warn!("Failed to import cookie: {}", e);
+        }
+    }
+    if !store
+        .iter_unexpired()
+        .any(|cookie| cookie.name() == LOGIN_COOKIE_NAME)
+    {
+        bail!("未找到有效的{}", LOGIN_COOKIE_NAME);
+    }
+    Ok(store)
+}
+
+fn is_netscape_format(content: &str) -> bool {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .any(|line| line.starts_with("# Netscape") || line.split('\t').count() == 7)
+}
+
+/// 将Netscape格式的cookie转换为Set-Cookie字符串
+fn parse_netscape(content: &str) -> Vec<String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    content
+        .lines()
+        .filter_map(|line| {
+            // curl和部分浏览器扩展会用`#HttpOnly_`前缀标记HttpOnly的cookie
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(v) => (v, true),
+                None => (line, false),
+            };
+            if line.starts_with('#') {
+                return None;
+            }
+            let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+            let [domain, _, path, secure, expires, name, value] = fields[..] else {
+                return None;
+            };
+            if !is_cookie_domain(domain) {
+                return None;
+            }
+            let max_age = match expires.parse::<u64>().unwrap_or(0) {
+                0 => RAW_COOKIE_MAX_AGE,
+                v if v > now => v - now,
+                _ => return None,
+            };
+            let mut set_cookie = format!(
+                "{}={}; Domain={}; Path={}; Max-Age={}",
+                name, value, domain, path, max_age
+            );
+            if secure.eq_ignore_ascii_case("TRUE") {
+                set_cookie.push_str("; Secure");
+            }
+            if http_only {
+                set_cookie.push_str("; HttpOnly");
+            }
+            Some(set_cookie)
+        })
+        .collect()
+}
+
+/// 是否为`163.com`或其子域名，不接受`evil163.com`这样的域名
+fn is_cookie_domain(domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+    domain == COOKIE_DOMAIN || domain.ends_with(&format!(".{}", COOKIE_DOMAIN))
+}
+
+/// 将`name=value; name=value`形式的原始cookie字符串转换为Set-Cookie字符串
+fn parse_raw(content: &str) -> Vec<String> {
+    let content = content.trim();
+    let content = content.strip_prefix("Cookie:").unwrap_or(content);
+    content
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            Some(format!(
+                "{}={}; Domain={}; Path=/; Max-Age={}",
+                name,
+                value.trim(),
+                COOKIE_DOMAIN,
+                RAW_COOKIE_MAX_AGE
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_raw() {
+        let store = parse_cookies("MUSIC_U=abc; __csrf=def").unwrap();
+        let url = Url::parse(COOKIE_URL).unwrap();
+        let values: Vec<(&str, &str)> = store.get_request_values(&url).collect();
+        assert!(values.contains(&("MUSIC_U", "abc")));
+        assert!(values.contains(&("__csrf", "def")));
+    }
+
+    #[test]
+    fn test_parse_netscape() {
+        let content = "# Netscape HTTP Cookie File\n\
+            #HttpOnly_.music.163.com\tTRUE\t/\tTRUE\t0\tMUSIC_U\tabc\n\
+            .example.com\tTRUE\t/\tFALSE\t0\tother\tvalue\n\
+            .evil163.com\tTRUE\t/\tFALSE\t0\tMUSIC_A\tvalue\n";
+        let store = parse_cookies(content).unwrap();
+        assert_eq!(store.iter_unexpired().count(), 1);
+        assert!(is_cookie_domain("163.com"));
+        assert!(!is_cookie_domain("evil163.com"));
+    }
+
+    #[test]
+    fn test_missing_login_cookie() {
+        assert!(parse_cookies("__csrf=def").is_err());
+    }
+}
@@ -0,0 +1,401 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::{
+    track::Track,
+    util::{self, FilenameRules},
+};
+
+/// 模板中可用的占位符
+pub const PLACEHOLDERS: [&str; 13] = [
+    "title",
+    "translated",
+    "artists",
+    "artist",
+    "album",
+    "album_artist",
+    "track",
+    "disc",
+    "year",
+    "playlist",
+    "index",
+    "id",
+    "quality",
+];
+/// 需要请求歌曲详情才能得到的占位符
+const DETAIL_PLACEHOLDERS: [&str; 4] = ["track", "disc", "year", "album_artist"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Placeholder(String),
+    /// `<...>` 括起来的部分，其中有占位符为空时整段省略
+    Optional(Vec<Segment>),
+}
+
+/// 文件夹和文件名模板
+///
+/// 占位符写作 `{title}`，用 `/` 分隔多级文件夹，每一级单独清理非法字符并截断
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    dir: Vec<Vec<Segment>>,
+    file: Vec<Vec<Segment>>,
+}
+
+/// 渲染后的路径
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedPath {
+    /// 相对于输出目录的文件夹
+    pub dir: PathBuf,
+    /// 不含扩展名的文件名
+    pub stem: String,
+}
+
+impl RenderedPath {
+    /// 加上扩展名后的文件路径
+    pub fn file(&self, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", self.stem, extension))
+    }
+}
+
+/// 各占位符对应的值
+#[derive(Debug, Default)]
+pub struct TemplateValues(HashMap<&'static str, String>);
+
+impl TemplateValues {
+    pub fn new(track: &Track, playlist: &str, playlist_len: usize, quality: Option<&str>) -> Self {
+        let index_width = playlist_len.to_string().len();
+        let mut values = HashMap::new();
+        values.insert("title", track.title.clone());
+        values.insert(
+            "translated",
+            track.translated_title.clone().unwrap_or_default(),
+        );
+        values.insert("artists", track.artists.join(", "));
+        values.insert("artist", track.artists.first().cloned().unwrap_or_default());
+        values.insert("album", track.album.clone());
+        values.insert(
+            "album_artist",
+            track.album_artist.clone().unwrap_or_default(),
+        );
+        values.insert(
+            "track",
+            track
+                .track_number
+                .map(|v| format!("{:02}", v))
+                .unwrap_or_default(),
+        );
+        values.insert(
+            "disc",
+            track.disc_number.map(|v| v.to_string()).unwrap_or_default(),
+        );
+        values.insert(
+            "year",
+            track.year.map(|v| v.to_string()).unwrap_or_default(),
+        );
+        values.insert("playlist", playlist.to_string());
+        values.insert(
+            "index",
+            format!("{:0width$}", track.playlist_index, width = index_width),
+        );
+        values.insert("id", track.id.to_string());
+        values.insert("quality", quality.unwrap_or_default().to_string());
+        Self(values)
+    }
+
+    fn get(&self, placeholder: &str) -> &str {
+        self.0
+            .get(placeholder)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+}
+
+impl PathTemplate {
+    pub fn new(dir: &str, file: &str) -> Result<Self, String> {
+        let file = parse_components(file)?;
+        // 只有 `/` 的模板去掉空的部分后也为空
+        if file.is_empty() {
+            return Err("文件名模板不能为空".to_string());
+        }
+        Ok(Self {
+            dir: parse_components(dir)?,
+            file,
+        })
+    }
+
+    /// 模板中是否使用了该占位符
+    pub fn uses(&self, placeholder: &str) -> bool {
+        self.dir
+            .iter()
+            .chain(self.file.iter())
+            .any(|component| contains(component, placeholder))
+    }
+
+    /// 歌曲所在的文件夹是否为专辑文件夹，即最后一级文件夹使用了 `{album}`
+    pub fn is_album_dir(&self) -> bool {
+        self.dir_components()
+            .last()
+            .is_some_and(|component| contains(component, "album"))
+    }
+
+    /// 歌手文件夹，即最后一个使用了 `{album_artist}` 或 `{artist}` 的文件夹
+    ///
+    /// 返回相对于输出目录的路径和使用的占位符
+    pub fn artist_dir(
+        &self,
+        values: &TemplateValues,
+        max_length: usize,
+        rules: &FilenameRules,
+    ) -> Option<(PathBuf, &'static str)> {
+        let components: Vec<&Vec<Segment>> = self.dir_components().collect();
+        let (index, placeholder) = components.iter().enumerate().rev().find_map(|(i, c)| {
+            ["album_artist", "artist"]
+                .into_iter()
+                .find(|placeholder| contains(c, placeholder))
+                .map(|placeholder| (i, placeholder))
+        })?;
+        let mut dir = PathBuf::new();
+        for component in &components[..=index] {
+            let component = util::truncate_filename(&render(component, values), max_length, rules);
+            if !component.is_empty() {
+                dir.push(component);
+            }
+        }
+        Some((dir, placeholder))
+    }
+
+    /// 所有文件夹级别的模板，包括文件名模板中 `/` 之前的部分
+    fn dir_components(&self) -> impl Iterator<Item = &Vec<Segment>> {
+        let (_, file_dirs) = self.file.split_last().unwrap();
+        self.dir.iter().chain(file_dirs.iter())
+    }
+
+    /// 模板中是否使用了需要歌曲详情的占位符
+    pub fn needs_detail(&self) -> bool {
+        DETAIL_PLACEHOLDERS.iter().any(|v| self.uses(v))
+    }
+
+    /// 渲染出相对于输出目录的路径，每一级都会按 `rules` 清理非法字符并截断到 `max_length`
+    pub fn render(
+        &self,
+        values: &TemplateValues,
+        max_length: usize,
+        rules: &FilenameRules,
+    ) -> RenderedPath {
+        let render_component = |segments: &Vec<Segment>| {
+            util::truncate_filename(&render(segments, values), max_length, rules)
+        };
+        let mut dir = PathBuf::new();
+        for component in &self.dir {
+            let component = render_component(component);
+            if !component.is_empty() {
+                dir.push(component);
+            }
+        }
+        let (stem, file_dirs) = self.file.split_last().unwrap();
+        for component in file_dirs {
+            let component = render_component(component);
+            if !component.is_empty() {
+                dir.push(component);
+            }
+        }
+        let mut stem = render_component(stem);
+        if stem.is_empty() {
+            stem = values.get("id").to_string();
+        }
+        RenderedPath { dir, stem }
+    }
+}
+
+fn contains(segments: &[Segment], placeholder: &str) -> bool {
+    segments.iter().any(|segment| match segment {
+        Segment::Placeholder(v) => v == placeholder,
+        Segment::Optional(v) => contains(v, placeholder),
+        Segment::Text(_) => false,
+    })
+}
+
+fn parse_components(template: &str) -> Result<Vec<Vec<Segment>>, String> {
+    template
+        .split('/')
+        .filter(|component| !component.trim().is_empty())
+        .map(parse)
+        .collect()
+}
+
+fn parse(template: &str) -> Result<Vec<Segment>, String> {
+    let mut stack: Vec<Vec<Segment>> = vec![Vec::new()];
+    let mut text = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("模板 {} 中的 {{ 没有闭合", template)),
+                    }
+                }
+                let name = name.trim();
+                if !PLACEHOLDERS.contains(&name) {
+                    return Err(format!(
+                        "未知的占位符 {{{}}}，可用的占位符：{}",
+                        name,
+                        PLACEHOLDERS.join(", ")
+                    ));
+                }
+                let segments = stack.last_mut().unwrap();
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                segments.push(Segment::Placeholder(name.to_string()));
+            }
+            '}' => return Err(format!("模板 {} 中的 }} 没有对应的 {{", template)),
+            '<' => {
+                if stack.len() > 1 {
+                    return Err(format!("模板 {} 中的 < 不能嵌套", template));
+                }
+                if !text.is_empty() {
+                    stack
+                        .last_mut()
+                        .unwrap()
+                        .push(Segment::Text(std::mem::take(&mut text)));
+                }
+                stack.push(Vec::new());
+            }
+            '>' => {
+                if stack.len() < 2 {
+                    return Err(format!("模板 {} 中的 > 没有对应的 <", template));
+                }
+                let mut segments = stack.pop().unwrap();
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                stack.last_mut().unwrap().push(Segment::Optional(segments));
+            }
+            c => text.push(c),
+        }
+    }
+    if stack.len() > 1 {
+        return Err(format!("模板 {} 中的 < 没有闭合", template));
+    }
+    let mut segments = stack.pop().unwrap();
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+fn render(segments: &[Segment], values: &TemplateValues) -> String {
+    let mut result = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Text(v) => result.push_str(v),
+            Segment::Placeholder(v) => result.push_str(values.get(v)),
+            Segment::Optional(v) => {
+                let has_empty = v.iter().any(|segment| {
+                    matches!(segment, Segment::Placeholder(name) if values.get(name).is_empty())
+                });
+                if !has_empty {
+                    result.push_str(&render(v, values));
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> Track {
+        Track {
+            id: 42,
+            title: "Song".to_string(),
+            artists: vec!["A".to_string(), "B".to_string()],
+            album: "Album".to_string(),
+            track_number: Some(3),
+            playlist_index: 7,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let template = PathTemplate::new(
+            "{playlist}/{album}",
+            "{index} {title}<({translated})> - {artists}",
+        )
+        .unwrap();
+        let values = TemplateValues::new(&track(), "List: 1", 120, None);
+        let path = template.render(&values, 200, &FilenameRules::default());
+        assert_eq!(path.dir, PathBuf::from("List 1").join("Album"));
+        assert_eq!(path.stem, "007 Song - A, B");
+        assert_eq!(
+            path.file("flac"),
+            PathBuf::from("List 1/Album/007 Song - A, B.flac")
+        );
+        assert!(!template.needs_detail());
+    }
+
+    #[test]
+    fn test_render_with_rules() {
+        let template = PathTemplate::new("{playlist}", "{title}").unwrap();
+        let mut track = track();
+        track.title = "AC/DC: Live".to_string();
+        let values = TemplateValues::new(&track, "-Best-", 1, None);
+        let rules = FilenameRules::new(util::FilenameTarget::Linux, "_");
+        let path = template.render(&values, 200, &rules);
+        assert_eq!(path.dir, PathBuf::from("_Best-"));
+        assert_eq!(path.stem, "AC_DC: Live");
+    }
+
+    #[test]
+    fn test_optional_segment() {
+        let template = PathTemplate::new("", "<{track}. >{title}<({translated})>").unwrap();
+        let mut track = track();
+        track.translated_title = Some("歌".to_string());
+        let values = TemplateValues::new(&track, "", 1, Some("lossless"));
+        assert_eq!(
+            template
+                .render(&values, 200, &FilenameRules::default())
+                .stem,
+            "03. Song(歌)"
+        );
+        assert!(template.needs_detail());
+    }
+
+    #[test]
+    fn test_artist_and_album_dir() {
+        let rules = FilenameRules::default();
+        let template = PathTemplate::new("{album_artist}/{year} - {album}", "{title}").unwrap();
+        let mut track = track();
+        track.album_artist = Some("Band".to_string());
+        let values = TemplateValues::new(&track, "", 1, None);
+        assert!(template.is_album_dir());
+        assert_eq!(
+            template.artist_dir(&values, 200, &rules),
+            Some((PathBuf::from("Band"), "album_artist"))
+        );
+        let template = PathTemplate::new("{playlist}", "{artist}/{title}").unwrap();
+        assert!(!template.is_album_dir());
+        assert_eq!(
+            template.artist_dir(&values, 200, &rules),
+            Some((PathBuf::from("A"), "artist"))
+        );
+        let template = PathTemplate::new("{playlist}", "{title}").unwrap();
+        assert_eq!(template.artist_dir(&values, 200, &rules), None);
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        assert!(PathTemplate::new("{playlist", "{title}").is_err());
+        assert!(PathTemplate::new("", "{name}").is_err());
+        assert!(PathTemplate::new("", "<<{title}>>").is_err());
+        assert!(PathTemplate::new("{playlist}", "").is_err());
+        assert!(PathTemplate::new("{playlist}", " / /").is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ncm_api::SongInfo;
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::{
+    api::{AlbumDetail, ExtraApi, SongDetail},
+    lyrics, util,
+};
+
+/// 歌曲的完整信息，合并歌单中的信息以及歌曲、专辑详情
+#[derive(Debug, Clone, Default)]
+pub struct Track {
+    pub id: u64,
+    pub title: String,
+    pub translated_title: Option<String>,
+    pub artists: Vec<String>,
+    /// 第一个歌手的Id，取自歌曲详情
+    pub artist_id: Option<u64>,
+    pub album: String,
+    pub album_id: u64,
+    pub album_artist: Option<String>,
+    pub album_artist_id: Option<u64>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub year: Option<i32>,
+    /// 发行日期(年, 月, 日)
+    pub release_date: Option<(i32, u32, u32)>,
+    pub genre: Option<String>,
+    /// 作曲，取自歌词开头的署名
+    pub composer: Option<String>,
+    /// 作词，取自歌词开头的署名
+    pub lyricist: Option<String>,
+    /// 时长(毫秒)
+    pub duration: u64,
+    pub cover_url: String,
+    /// 在歌单中的位置，从1开始
+    pub playlist_index: usize,
+}
+
+impl Track {
+    pub fn from_song_info(info: &SongInfo, playlist_index: usize) -> Self {
+        Self {
+            id: info.id,
+            title: info.name.clone(),
+            translated_title: info.translated_name.clone(),
+            artists: info.singer.clone(),
+            album: info.album.clone(),
+            album_id: info.album_id,
+            duration: info.duration,
+            cover_url: info.pic_url.clone(),
+            playlist_index,
+            ..Default::default()
+        }
+    }
+
+    /// 只有歌曲详情时使用，例如为已有的文件重新写入标签
+    pub fn from_detail(detail: &SongDetail) -> Self {
+        let mut track = Self {
+            id: detail.id,
+            title: detail.name.clone(),
+            artists: detail.artists.iter().map(|v| v.name.clone()).collect(),
+            album: detail
+                .album
+                .as_ref()
+                .map(|v| v.name.clone())
+                .unwrap_or_default(),
+            album_id: detail.album.as_ref().map(|v| v.id).unwrap_or_default(),
+            duration: detail.duration,
+            cover_url: detail
+                .album
+                .as_ref()
+                .map(|v| v.pic_url.clone())
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        track.apply_detail(detail);
+        track
+    }
+
+    /// 补充歌曲详情中的曲目序号、碟片序号、发行年份和歌手Id
+    pub fn apply_detail(&mut self, detail: &SongDetail) {
+        self.track_number = Some(detail.track_number).filter(|&v| v > 0);
+        self.disc_number = detail
+            .disc
+            .as_deref()
+            .and_then(|v| v.trim().parse::<u32>().ok())
+            .filter(|&v| v > 0);
+        self.release_date = util::date_from_timestamp(detail.publish_time);
+        self.year = self.release_date.map(|(year, _, _)| year);
+        self.artist_id = detail.artists.first().map(|v| v.id).filter(|&v| v > 0);
+    }
+
+    /// 补充专辑详情中的专辑歌手、曲目数、碟片数和风格
+    pub fn apply_album(&mut self, album: &AlbumDetail) {
+        self.album_artist = album
+            .artist
+            .as_ref()
+            .map(|artist| artist.name.clone())
+            .filter(|name| !name.is_empty());
+        self.album_artist_id = album
+            .artist
+            .as_ref()
+            .map(|artist| artist.id)
+            .filter(|&v| v > 0);
+        self.track_total = Some(album.size).filter(|&v| v > 0);
+        self.disc_total = Some(album.disc_total).filter(|&v| v > 0);
+        self.genre = album
+            .tags
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string);
+        if self.release_date.is_none() {
+            self.release_date = util::date_from_timestamp(album.publish_time);
+            self.year = self.release_date.map(|(year, _, _)| year);
+        }
+    }
+
+    /// 从歌词开头的 `作词 : xxx`、`作曲 : xxx` 或JSON格式的署名中取出作词和作曲
+    pub fn apply_lyric_credits(&mut self, lyric: &str) {
+        for line in lyric.lines() {
+            let text = lyrics::line_text(line);
+            let Some((key, value)) = text.split_once([':', '：']) else {
+                continue;
+            };
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match key.trim() {
+                "作词" | "词" if self.lyricist.is_none() => {
+                    self.lyricist = Some(value.to_string())
+                }
+                "作曲" | "曲" if self.composer.is_none() => {
+                    self.composer = Some(value.to_string())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// 发行日期，格式为 `YYYY-MM-DD`
+    pub fn release_date_string(&self) -> Option<String> {
+        self.release_date
+            .map(|(year, month, day)| format!("{:04}-{:02}-{:02}", year, month, day))
+    }
+
+    /// 用于提示信息的歌曲名称
+    pub fn display_name(&self) -> String {
+        format!(
+            "{}{} - {}",
+            self.title,
+            match &self.translated_title {
+                Some(v) => format!("({})", v),
+                None => String::new(),
+            },
+            self.artists.join(", ")
+        )
+    }
+}
+
+/// 缓存中的一张专辑详情，请求完成前为空
+type AlbumSlot = Arc<OnceCell<Option<Arc<AlbumDetail>>>>;
+
+/// 按专辑Id缓存专辑详情，同一张专辑只请求一次
+pub struct AlbumCache {
+    api: Arc<ExtraApi>,
+    albums: Mutex<HashMap<u64, AlbumSlot>>,
+}
+
+impl AlbumCache {
+    pub fn new(api: Arc<ExtraApi>) -> Self {
+        Self {
+            api,
+            albums: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 获取专辑详情，请求失败时返回 `None` 且不再重复请求
+    pub async fn get(&self, id: u64) -> Option<Arc<AlbumDetail>> {
+        // 每张专辑单独加锁，不同专辑可以同时请求
+        let cell = self.albums.lock().await.entry(id).or_default().clone();
+        cell.get_or_init(|| async {
+            match self.api.album(id).await {
+                Ok(v) => Some(Arc::new(v)),
+                Err(e) => {
+                    log::warn!("Failed to get album {}: {}", id, e);
+                    None
+                }
+            }
+        })
+        .await
+        .clone()
+    }
+}
+
+/// 缓存中的一个歌手图片链接，请求完成前为空
+type PictureSlot = Arc<OnceCell<Option<String>>>;
+
+/// 按歌手Id缓存歌手图片链接
+pub struct ArtistCache {
+    api: Arc<ExtraApi>,
+    pictures: Mutex<HashMap<u64, PictureSlot>>,
+}
+
+impl ArtistCache {
+    pub fn new(api: Arc<ExtraApi>) -> Self {
+        Self {
+            api,
+            pictures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 获取歌手图片链接，请求失败或没有图片时返回 `None` 且不再重复请求
+    pub async fn picture_url(&self, id: u64) -> Option<String> {
+        // 每个歌手单独加锁，不同歌手可以同时请求
+        let cell = self.pictures.lock().await.entry(id).or_default().clone();
+        cell.get_or_init(|| async {
+            match self.api.artist(id).await {
+                Ok(v) => v.pic_url.filter(|v| !v.is_empty()),
+                Err(e) => {
+                    log::warn!("Failed to get artist {}: {}", id, e);
+                    None
+                }
+            }
+        })
+        .await
+        .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lyric_credits() {
+        let mut track = Track::default();
+        track.apply_lyric_credits(
+            "[00:00.00] 作词 : 方文山\n[00:01.00] 作曲：周杰伦\n[00:02.00]编曲 : 林迈可\n[00:10.00]歌词: 第一句",
+        );
+        assert_eq!(track.lyricist.as_deref(), Some("方文山"));
+        assert_eq!(track.composer.as_deref(), Some("周杰伦"));
+
+        let mut track = Track::default();
+        track.apply_lyric_credits(
+            "{\"t\":0,\"c\":[{\"tx\":\"作词: \"},{\"tx\":\"方文山\"}]}\n\
+            {\"t\":1000,\"c\":[{\"tx\":\"作曲: \"},{\"tx\":\"周杰伦\"}]}",
+        );
+        assert_eq!(track.lyricist.as_deref(), Some("方文山"));
+        assert_eq!(track.composer.as_deref(), Some("周杰伦"));
+    }
+}
@@ -1,40 +1,256 @@
+use serde::{Deserialize, Serialize};
 use unicode_segmentation::UnicodeSegmentation;
 
-const SPECIAL_CHARS: [&str; 9] = ["/", ":", "*", "?", "\"", "<", ">", "|", "\\"];
+const SPECIAL_CHARS: [char; 9] = ['/', ':', '*', '?', '"', '<', '>', '|', '\\'];
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// 文件系统对文件名长度的限制
+const MAX_NAME_UNITS: usize = 255;
+/// 为扩展名和 ` [preview]` 等后缀预留的长度
+const RESERVED_NAME_UNITS: usize = 32;
+const ELLIPSIS: &str = "...";
+/// 替换文件名开头的 `-` 时，替换内容不可用的情况下使用的字符
+const FALLBACK_REPLACEMENT: &str = "_";
 
-/// 清理文件名中的非法字符
-pub fn sanitize_filename(filename: &str) -> String {
-    UnicodeSegmentation::graphemes(filename, true)
-        .map(|g| if !SPECIAL_CHARS.contains(&g) { g } else { "" })
-        .collect::<String>()
-        .trim()
-        .to_string()
+/// 文件名需要兼容的文件系统
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilenameTarget {
+    /// 同时满足以下所有文件系统的限制
+    #[default]
+    Portable,
+    Windows,
+    Macos,
+    Linux,
+    /// 与Windows的限制相同，用于U盘、SD卡等
+    Fat32,
 }
 
-/// 截断文件名，保留头部和尾部，中间用 "..." 连接
-pub fn truncate_filename(filename: &str, max_length: usize) -> String {
-    let filename = sanitize_filename(filename);
+/// 文件名清理规则
+#[derive(Debug, Clone, Default)]
+pub struct FilenameRules {
+    pub target: FilenameTarget,
+    /// 替换非法字符的字符串，为空时直接删除非法字符
+    pub replacement: String,
+}
+
+impl FilenameRules {
+    pub fn new(target: FilenameTarget, replacement: &str) -> Self {
+        Self {
+            target,
+            replacement: replacement.to_string(),
+        }
+    }
+
+    fn is_windows_like(&self) -> bool {
+        matches!(
+            self.target,
+            FilenameTarget::Portable | FilenameTarget::Windows | FilenameTarget::Fat32
+        )
+    }
+
+    /// 目标文件系统的文件名是否不区分大小写
+    pub fn case_insensitive(&self) -> bool {
+        self.target != FilenameTarget::Linux
+    }
+
+    /// 是否为目标文件系统不允许的字符
+    pub fn is_forbidden(&self, c: char) -> bool {
+        if c.is_control() {
+            return true;
+        }
+        match self.target {
+            FilenameTarget::Linux => c == '/',
+            FilenameTarget::Macos => c == '/' || c == ':',
+            _ => SPECIAL_CHARS.contains(&c),
+        }
+    }
+
+    /// 按目标文件系统的计算方式得到的长度，Windows按UTF-16计算，其它按UTF-8字节计算
+    fn units(&self, s: &str) -> usize {
+        match self.target {
+            FilenameTarget::Windows | FilenameTarget::Fat32 => s.encode_utf16().count(),
+            _ => s.len(),
+        }
+    }
+}
+
+/// 按指定规则清理文件名中的非法字符、保留名称以及开头的 `-` 和结尾的 `.`
+pub fn sanitize_filename(filename: &str, rules: &FilenameRules) -> String {
+    let mut result = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        if rules.is_forbidden(c) {
+            result.push_str(&rules.replacement);
+        } else {
+            result.push(c);
+        }
+    }
+    let mut result = result.trim().to_string();
+    // 避免文件名被命令行程序当作参数
+    if result.starts_with('-') {
+        let trimmed = result.trim_start_matches('-');
+        // 替换内容本身以 `-` 开头时改用 `_`
+        let replacement = if rules.replacement.starts_with('-') {
+            FALLBACK_REPLACEMENT
+        } else {
+            rules.replacement.as_str()
+        };
+        result = format!(
+            "{}{}",
+            replacement.repeat(result.len() - trimmed.len()),
+            trimmed
+        );
+    }
+    if rules.is_windows_like() {
+        result = result.trim_end_matches(['.', ' ']).to_string();
+        let stem = result.split('.').next().unwrap_or_default().trim_end();
+        if WINDOWS_RESERVED_NAMES
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(stem))
+        {
+            result.insert(stem.len(), '_');
+        }
+    }
+    if result == "." || result == ".." {
+        return String::new();
+    }
+    result
+}
+
+/// 按指定规则清理并截断文件名，保留头部和尾部，中间用 "..." 连接，同时限制字符数 `max_length` 和文件系统的长度限制
+pub fn truncate_filename(filename: &str, max_length: usize, rules: &FilenameRules) -> String {
+    let filename = sanitize_filename(filename, rules);
+    let max_units = MAX_NAME_UNITS - RESERVED_NAME_UNITS;
 
     let graphemes: Vec<&str> = UnicodeSegmentation::graphemes(filename.as_str(), true).collect();
     let total_len = graphemes.len();
 
-    if total_len <= max_length {
+    if total_len <= max_length && rules.units(&filename) <= max_units {
         return filename;
     }
 
-    let ellipsis = "...";
-    let ellipsis_len = UnicodeSegmentation::graphemes(ellipsis, true).count();
+    let ellipsis_len = UnicodeSegmentation::graphemes(ELLIPSIS, true).count();
     let available_len = max_length.saturating_sub(ellipsis_len);
+    let available_units = max_units - rules.units(ELLIPSIS);
 
     if available_len == 0 {
-        return ellipsis.to_string();
+        return ELLIPSIS.to_string();
     }
 
     let head_len = available_len / 2;
     let tail_len = available_len - head_len;
+    let head_units = available_units / 2;
+    let tail_units = available_units - head_units;
+
+    let head: String =
+        take_graphemes(graphemes.iter().copied(), head_len, head_units, rules).concat();
+    let mut tail = take_graphemes(graphemes.iter().rev().copied(), tail_len, tail_units, rules);
+    tail.reverse();
+    let tail: String = tail.concat();
+
+    format!("{}{}{}", head, ELLIPSIS, tail)
+}
+
+/// 依次取出字形簇，直到数量或长度超出限制
+fn take_graphemes<'a>(
+    graphemes: impl Iterator<Item = &'a str>,
+    max_len: usize,
+    max_units: usize,
+    rules: &FilenameRules,
+) -> Vec<&'a str> {
+    let mut result = Vec::new();
+    let mut units = 0;
+    for g in graphemes.take(max_len) {
+        units += rules.units(g);
+        if units > max_units {
+            break;
+        }
+        result.push(g);
+    }
+    result
+}
+
+/// 网易云音乐的时间戳以北京时间为准
+const TIMESTAMP_OFFSET_MS: i64 = 8 * 60 * 60 * 1000;
+
+/// 将毫秒时间戳转换为 (年, 月, 日)，时间戳为0时表示未知，返回 `None`
+pub fn date_from_timestamp(timestamp_ms: i64) -> Option<(i32, u32, u32)> {
+    if timestamp_ms == 0 {
+        return None;
+    }
+    let days = (timestamp_ms + TIMESTAMP_OFFSET_MS).div_euclid(24 * 60 * 60 * 1000);
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+    Some((year, month, day))
+}
 
-    let head: String = graphemes[..head_len].concat();
-    let tail: String = graphemes[total_len.saturating_sub(tail_len)..].concat();
+/// 将毫秒时间戳转换为北京时间 `YYYY-MM-DD HH:MM:SS`，时间戳为0时返回 `None`
+pub fn datetime_from_timestamp(timestamp_ms: i64) -> Option<String> {
+    let (year, month, day) = date_from_timestamp(timestamp_ms)?;
+    let seconds = (timestamp_ms + TIMESTAMP_OFFSET_MS).rem_euclid(24 * 60 * 60 * 1000) / 1000;
+    Some(format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    ))
+}
 
-    format!("{}{}{}", head, ellipsis, tail)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_datetime_from_timestamp() {
+        assert_eq!(
+            datetime_from_timestamp(1_704_135_845_000).as_deref(),
+            Some("2024-01-02 03:04:05")
+        );
+        assert_eq!(datetime_from_timestamp(0), None);
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        let windows = FilenameRules::new(FilenameTarget::Windows, "_");
+        assert_eq!(sanitize_filename("a/b:c\u{7}d", &windows), "a_b_c_d");
+        assert_eq!(sanitize_filename("con", &windows), "con_");
+        assert_eq!(sanitize_filename("NUL.txt", &windows), "NUL_.txt");
+        assert_eq!(sanitize_filename("Song... ", &windows), "Song");
+        assert_eq!(sanitize_filename("--ing", &windows), "__ing");
+        let dash = FilenameRules::new(FilenameTarget::Windows, "-");
+        assert_eq!(sanitize_filename("-a:b", &dash), "_a-b");
+        let linux = FilenameRules::new(FilenameTarget::Linux, "");
+        assert_eq!(sanitize_filename("a/b:c.", &linux), "ab:c.");
+        assert_eq!(sanitize_filename("CON", &linux), "CON");
+        assert_eq!(sanitize_filename("a?b", &FilenameRules::default()), "ab");
+    }
+
+    #[test]
+    fn test_truncate_filename_by_bytes() {
+        let name = "歌".repeat(150);
+        let truncated = truncate_filename(&name, 200, &FilenameRules::default());
+        assert!(truncated.len() <= MAX_NAME_UNITS - RESERVED_NAME_UNITS);
+        assert!(truncated.contains(ELLIPSIS));
+        let windows = FilenameRules::new(FilenameTarget::Windows, "");
+        assert_eq!(truncate_filename(&name, 200, &windows), name);
+        assert_eq!(
+            truncate_filename(&"a".repeat(300), 200, &FilenameRules::default())
+                .chars()
+                .count(),
+            200
+        );
+    }
 }